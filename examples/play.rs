@@ -1,11 +1,13 @@
 use std::time::Duration;
 
-use kdmapi::KDMAPI;
+use kdmapi::{ShortMessage, KDMAPI};
 
 fn main() {
-    let kdmapi = KDMAPI.open_stream();
+    let kdmapi = KDMAPI.as_ref().unwrap().open_stream().unwrap();
 
-    kdmapi.send_direct_data(0x7F4090);
+    kdmapi
+        .send(ShortMessage::note_on(0, 0x40, 0x7F).unwrap())
+        .unwrap();
 
     std::thread::sleep(Duration::from_secs(5));
 
@@ -0,0 +1,20 @@
+use std::{thread, time::Duration};
+
+use kdmapi::{smf::Smf, MidiPlayer, PlaybackState, KDMAPI};
+
+fn main() {
+    let path = std::env::args()
+        .nth(1)
+        .expect("usage: play_midi <file.mid>");
+    let smf = Smf::open(path).unwrap();
+
+    let stream = KDMAPI.as_ref().unwrap().open_stream().unwrap();
+    let player = MidiPlayer::new(stream, &smf);
+    player.play();
+
+    while player.state() == PlaybackState::Playing {
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    // player dropped, returning and dropping the stream here
+}
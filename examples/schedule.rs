@@ -0,0 +1,24 @@
+use std::time::{Duration, Instant};
+
+use kdmapi::{Scheduler, ShortMessage, KDMAPI};
+
+fn main() {
+    let stream = KDMAPI.as_ref().unwrap().open_stream().unwrap();
+    let scheduler = Scheduler::new(stream);
+
+    // An arpeggio, one note every 250 ms, each held for 200 ms
+    let start = Instant::now();
+    for (i, key) in [0x3C, 0x40, 0x43, 0x48].iter().enumerate() {
+        let at = start + Duration::from_millis(250) * i as u32;
+        scheduler.schedule(at, ShortMessage::note_on(0, *key, 0x7F).unwrap());
+        scheduler.schedule(
+            at + Duration::from_millis(200),
+            ShortMessage::note_off(0, *key, 0).unwrap(),
+        );
+    }
+    scheduler.wait_idle();
+
+    println!("{:?}", scheduler.stats());
+
+    // scheduler dropped, returning and dropping the stream here
+}
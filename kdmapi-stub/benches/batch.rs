@@ -0,0 +1,77 @@
+//! Compares sending short messages one by one with `send_batch`, against the
+//! stub. The stub does its own locking and logging per call, so the numbers
+//! show the overhead on our side rather than OmniMIDI's.
+//!
+//! `cargo bench` doesn't build the stub library itself, so run with
+//! `cargo build --release -p kdmapi-stub && cargo bench -p kdmapi-stub`.
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::Stub;
+use kdmapi::KDMAPIStream;
+
+const ROUNDS: u32 = 2000;
+
+/// A 128 note chord and its release, like a frame of a black MIDI
+fn frame() -> Vec<u32> {
+    let on = (0..128).map(|key| 0x400090 | key << 8);
+    let off = (0..128).map(|key| 0x000080 | key << 8);
+    on.chain(off).collect()
+}
+
+fn measure(stub: &Stub, name: &str, mut send: impl FnMut(&[u32])) -> Duration {
+    let frame = frame();
+    // Warm up
+    for _ in 0..ROUNDS / 10 {
+        send(&frame);
+    }
+    stub.clear_log();
+
+    let mut total = Duration::ZERO;
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        send(&frame);
+        total += start.elapsed();
+        // Keep the stub's log from growing across rounds
+        stub.clear_log();
+    }
+
+    let per_event = total / (ROUNDS * frame.len() as u32);
+    println!("{:<32} {:>8.1?} per event", name, per_event);
+    per_event
+}
+
+fn run(stub: &Stub, stream: &KDMAPIStream) {
+    measure(stub, "send_direct_data", |frame| {
+        for &data in frame {
+            stream.send_direct_data(data).unwrap();
+        }
+    });
+    measure(stub, "send_batch", |frame| {
+        stream.send_batch(frame).unwrap()
+    });
+    measure(stub, "send_direct_data_no_buf", |frame| {
+        for &data in frame {
+            stream.send_direct_data_no_buf(data).unwrap();
+        }
+    });
+    measure(stub, "send_batch_no_buf", |frame| {
+        stream.send_batch_no_buf(frame).unwrap()
+    });
+}
+
+fn main() {
+    let stub = Stub::new();
+    let mut stream = stub.binds().open_stream().unwrap();
+
+    println!("without tracking:");
+    run(&stub, &stream);
+
+    println!("with note and state tracking:");
+    stream.set_note_tracking(true);
+    stream.set_state_tracking(true);
+    run(&stub, &stream);
+}
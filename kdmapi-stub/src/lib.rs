@@ -0,0 +1,325 @@
+//! A stand-in for OmniMIDI's KDMAPI exports.
+//!
+//! Every call is appended to an in-memory text log, one line per call, which
+//! can be read back through the `KDMAPIStub_*` exports. The return values of
+//! the KDMAPI functions can be changed the same way to simulate failures.
+//!
+//! As with OmniMIDI, pointer arguments must be valid for the call.
+
+#![allow(non_snake_case, clippy::missing_safety_doc)]
+
+use std::{fmt::Write, slice, sync::Mutex};
+
+const MMSYSERR_ERROR: u32 = 1;
+const MHDR_DONE: u32 = 1;
+const MHDR_PREPARED: u32 = 2;
+const MMSYSERR_INVALPARAM: u32 = 11;
+const MIDIERR_UNPREPARED: u32 = 64;
+const OM_SET: u32 = 0;
+const OM_GET: u32 = 1;
+/// The first and last driver setting codes
+const OM_SETTINGS: std::ops::RangeInclusive<u32> = 0x10000..=0x10031;
+
+struct StubState {
+    log: String,
+    available: bool,
+    init_result: i32,
+    send_result: u32,
+    failing_sends: u32,
+    soundfont_result: bool,
+    /// Driver settings that have been set, as (setting, value)
+    settings: Vec<(u32, u32)>,
+}
+
+impl StubState {
+    const fn new() -> Self {
+        StubState {
+            log: String::new(),
+            available: true,
+            init_result: 1,
+            send_result: 0,
+            failing_sends: 0,
+            soundfont_result: true,
+            settings: Vec::new(),
+        }
+    }
+
+    fn log(&mut self, line: &str) {
+        self.log.push_str(line);
+        self.log.push('\n');
+    }
+
+    fn send_result(&mut self) -> u32 {
+        if self.failing_sends > 0 {
+            self.failing_sends -= 1;
+            MMSYSERR_ERROR
+        } else {
+            self.send_result
+        }
+    }
+}
+
+static STATE: Mutex<StubState> = Mutex::new(StubState::new());
+
+fn with_state<T>(f: impl FnOnce(&mut StubState) -> T) -> T {
+    let mut state = STATE.lock().unwrap_or_else(|err| err.into_inner());
+    f(&mut state)
+}
+
+#[repr(C)]
+pub struct DebugInfo {
+    rendering_time: f32,
+    active_voices: [u32; 16],
+    asio_input_latency: f64,
+    asio_output_latency: f64,
+}
+
+static DEBUG_INFO: DebugInfo = DebugInfo {
+    rendering_time: 12.5,
+    active_voices: [8, 0, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0],
+    asio_input_latency: 0.0,
+    asio_output_latency: 5.0,
+};
+
+#[repr(C)]
+pub struct MidiHdr {
+    data: *mut u8,
+    buffer_length: u32,
+    bytes_recorded: u32,
+    user: usize,
+    flags: u32,
+    next: *mut MidiHdr,
+    reserved: usize,
+    offset: u32,
+    reserved_array: [usize; 8],
+}
+
+#[no_mangle]
+pub extern "C" fn IsKDMAPIAvailable() -> bool {
+    with_state(|state| state.available)
+}
+
+#[no_mangle]
+pub extern "C" fn InitializeKDMAPIStream() -> i32 {
+    with_state(|state| {
+        state.log("init");
+        state.init_result
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn TerminateKDMAPIStream() -> i32 {
+    with_state(|state| {
+        state.log("terminate");
+        1
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn ResetKDMAPIStream() {
+    with_state(|state| state.log("reset"))
+}
+
+#[no_mangle]
+pub extern "C" fn SendDirectData(data: u32) -> u32 {
+    with_state(|state| {
+        state.log(&format!("short {:06x}", data));
+        state.send_result()
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn SendDirectDataNoBuf(data: u32) -> u32 {
+    with_state(|state| {
+        state.log(&format!("short_no_buf {:06x}", data));
+        state.send_result()
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn PrepareLongData(header: *mut MidiHdr, size: u32) -> u32 {
+    if header.is_null() || size as usize != std::mem::size_of::<MidiHdr>() {
+        return MMSYSERR_INVALPARAM;
+    }
+    (*header).flags |= MHDR_PREPARED;
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn UnprepareLongData(header: *mut MidiHdr, size: u32) -> u32 {
+    if header.is_null() || size as usize != std::mem::size_of::<MidiHdr>() {
+        return MMSYSERR_INVALPARAM;
+    }
+    (*header).flags &= !MHDR_PREPARED;
+    0
+}
+
+unsafe fn send_long(kind: &str, header: *mut MidiHdr, size: u32) -> u32 {
+    if header.is_null() || size as usize != std::mem::size_of::<MidiHdr>() {
+        return MMSYSERR_INVALPARAM;
+    }
+    let header = &mut *header;
+    if header.flags & MHDR_PREPARED == 0 {
+        return MIDIERR_UNPREPARED;
+    }
+
+    let data = slice::from_raw_parts(header.data, header.bytes_recorded as usize);
+    let mut line = String::from(kind);
+    line.push(' ');
+    for byte in data {
+        write!(line, "{:02x}", byte).unwrap();
+    }
+    header.flags |= MHDR_DONE;
+
+    with_state(|state| {
+        state.log(&line);
+        state.send_result()
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn SendDirectLongData(header: *mut MidiHdr, size: u32) -> u32 {
+    send_long("sysex", header, size)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn SendDirectLongDataNoBuf(header: *mut MidiHdr, size: u32) -> u32 {
+    send_long("sysex_no_buf", header, size)
+}
+
+#[cfg(target_os = "windows")]
+#[no_mangle]
+pub unsafe extern "C" fn LoadCustomSoundFontsList(path: *const u16) -> bool {
+    let len = (0..).take_while(|&i| *path.add(i) != 0).count();
+    load_soundfonts(&String::from_utf16_lossy(slice::from_raw_parts(path, len)))
+}
+
+#[cfg(not(target_os = "windows"))]
+#[no_mangle]
+pub unsafe extern "C" fn LoadCustomSoundFontsList(path: *const std::os::raw::c_char) -> bool {
+    load_soundfonts(&std::ffi::CStr::from_ptr(path).to_string_lossy())
+}
+
+/// Logs the list's path, followed by its lines if it exists
+fn load_soundfonts(path: &str) -> bool {
+    let contents = std::fs::read_to_string(path).unwrap_or_default();
+    with_state(|state| {
+        state.log(&format!("soundfonts {}", path));
+        for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+            state.log(&format!("  {}", line.trim()));
+        }
+        state.soundfont_result
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ReturnKDMAPIVer(
+    major: *mut u32,
+    minor: *mut u32,
+    build: *mut u32,
+    revision: *mut u32,
+) -> bool {
+    with_state(|state| state.log("version"));
+    *major = 4;
+    *minor = 1;
+    *build = 0;
+    *revision = 7;
+    true
+}
+
+/// Stores the value of any known setting, and reads back 0 for settings
+/// that weren't set
+#[no_mangle]
+pub unsafe extern "C" fn DriverSettings(
+    setting: u32,
+    mode: u32,
+    value: *mut std::ffi::c_void,
+    size: u32,
+) -> bool {
+    if value.is_null() || size != 4 || !OM_SETTINGS.contains(&setting) {
+        return false;
+    }
+    let value = value as *mut u32;
+    with_state(|state| {
+        let stored = state.settings.iter().position(|&(code, _)| code == setting);
+        match mode {
+            OM_GET => {
+                state.log(&format!("get_setting {:x}", setting));
+                *value = stored.map_or(0, |index| state.settings[index].1);
+                true
+            }
+            OM_SET => {
+                state.log(&format!("set_setting {:x} {}", setting, *value));
+                match stored {
+                    Some(index) => state.settings[index].1 = *value,
+                    None => state.settings.push((setting, *value)),
+                }
+                true
+            }
+            _ => false,
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn GetDriverDebugInfo() -> *const DebugInfo {
+    with_state(|state| state.log("debug_info"));
+    &DEBUG_INFO
+}
+
+/// Copies up to `capacity` bytes of the log into `buffer` and returns the
+/// full length of the log. Pass a null buffer to only query the length.
+#[no_mangle]
+pub unsafe extern "C" fn KDMAPIStub_ReadLog(buffer: *mut u8, capacity: usize) -> usize {
+    with_state(|state| {
+        let log = state.log.as_bytes();
+        if !buffer.is_null() {
+            let len = log.len().min(capacity);
+            std::ptr::copy_nonoverlapping(log.as_ptr(), buffer, len);
+        }
+        log.len()
+    })
+}
+
+/// Clears the log, keeping the configured return values
+#[no_mangle]
+pub extern "C" fn KDMAPIStub_ClearLog() {
+    with_state(|state| state.log.clear())
+}
+
+/// Clears the log and restores the default return values
+#[no_mangle]
+pub extern "C" fn KDMAPIStub_Reset() {
+    with_state(|state| *state = StubState::new())
+}
+
+/// Sets what `IsKDMAPIAvailable` returns
+#[no_mangle]
+pub extern "C" fn KDMAPIStub_SetAvailable(available: bool) {
+    with_state(|state| state.available = available)
+}
+
+/// Sets what `InitializeKDMAPIStream` returns
+#[no_mangle]
+pub extern "C" fn KDMAPIStub_SetInitResult(result: i32) {
+    with_state(|state| state.init_result = result)
+}
+
+/// Sets what the `SendDirect*` functions return
+#[no_mangle]
+pub extern "C" fn KDMAPIStub_SetSendResult(result: u32) {
+    with_state(|state| state.send_result = result)
+}
+
+/// Makes the next `count` calls to the `SendDirect*` functions fail with
+/// `MMSYSERR_ERROR`, regardless of the configured send result
+#[no_mangle]
+pub extern "C" fn KDMAPIStub_FailNextSends(count: u32) {
+    with_state(|state| state.failing_sends = count)
+}
+
+/// Sets what `LoadCustomSoundFontsList` returns
+#[no_mangle]
+pub extern "C" fn KDMAPIStub_SetSoundFontResult(result: bool) {
+    with_state(|state| state.soundfont_result = result)
+}
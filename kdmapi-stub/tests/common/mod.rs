@@ -0,0 +1,96 @@
+#![allow(dead_code)]
+
+use std::{
+    env::consts::{DLL_PREFIX, DLL_SUFFIX},
+    sync::{Mutex, MutexGuard},
+};
+
+use kdmapi::{KDMAPIBinds, KdmapiLoader};
+use libloading::Library;
+
+static LOCK: Mutex<()> = Mutex::new(());
+
+/// The stub's file name, which cargo places next to the test executable
+pub fn stub_file_name() -> String {
+    format!("{}kdmapi_stub{}", DLL_PREFIX, DLL_SUFFIX)
+}
+
+/// A loader that only finds the stub next to the test executable
+pub fn loader() -> KdmapiLoader {
+    KdmapiLoader::new()
+        .no_env_var()
+        .system_search(false)
+        .fallback_names(vec![stub_file_name()])
+}
+
+/// Handle to the stub's control exports.
+///
+/// The stub's state is global to the process, so tests hold a lock for as
+/// long as the handle is alive and start from a cleared log.
+pub struct Stub {
+    lib: Library,
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl Stub {
+    pub fn new() -> Self {
+        let guard = LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        let lib = loader().load().expect("failed to load the stub library");
+        let stub = Stub { lib, _guard: guard };
+        unsafe {
+            stub.get::<unsafe extern "C" fn()>(b"KDMAPIStub_Reset\0")();
+        }
+        stub
+    }
+
+    pub fn binds(&self) -> KDMAPIBinds {
+        loader()
+            .load_binds()
+            .expect("failed to load the stub bindings")
+    }
+
+    unsafe fn get<T: Copy>(&self, name: &[u8]) -> T {
+        *self.lib.get::<T>(name).unwrap()
+    }
+
+    /// Returns the log lines recorded since the last call and clears them
+    pub fn take_log(&self) -> Vec<String> {
+        let log = unsafe {
+            let read =
+                self.get::<unsafe extern "C" fn(*mut u8, usize) -> usize>(b"KDMAPIStub_ReadLog\0");
+            let mut buffer = vec![0; read(std::ptr::null_mut(), 0)];
+            let len = read(buffer.as_mut_ptr(), buffer.len());
+            buffer.truncate(len);
+            String::from_utf8(buffer).unwrap()
+        };
+        self.clear_log();
+        log.lines().map(str::to_owned).collect()
+    }
+
+    /// Clears the log, keeping the configured return values
+    pub fn clear_log(&self) {
+        unsafe { self.get::<unsafe extern "C" fn()>(b"KDMAPIStub_ClearLog\0")() }
+    }
+
+    pub fn set_available(&self, available: bool) {
+        unsafe { self.get::<unsafe extern "C" fn(bool)>(b"KDMAPIStub_SetAvailable\0")(available) }
+    }
+
+    pub fn set_init_result(&self, result: i32) {
+        unsafe { self.get::<unsafe extern "C" fn(i32)>(b"KDMAPIStub_SetInitResult\0")(result) }
+    }
+
+    pub fn set_send_result(&self, result: u32) {
+        unsafe { self.get::<unsafe extern "C" fn(u32)>(b"KDMAPIStub_SetSendResult\0")(result) }
+    }
+
+    pub fn fail_next_sends(&self, count: u32) {
+        unsafe { self.get::<unsafe extern "C" fn(u32)>(b"KDMAPIStub_FailNextSends\0")(count) }
+    }
+
+    pub fn set_soundfont_result(&self, result: bool) {
+        unsafe {
+            self.get::<unsafe extern "C" fn(bool)>(b"KDMAPIStub_SetSoundFontResult\0")(result)
+        }
+    }
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use common::Stub;
+use kdmapi::{
+    settings::{self, Setting},
+    KDMAPIBinds, KdmapiError, Version,
+};
+
+#[test]
+fn returns_the_kdmapi_version() {
+    let stub = Stub::new();
+    let binds = stub.binds();
+
+    let version = binds.kdmapi_version().unwrap();
+    assert_eq!(
+        version,
+        Version {
+            major: 4,
+            minor: 1,
+            build: 0,
+            revision: 7,
+        }
+    );
+    assert_eq!(version.to_string(), "4.1.0 Rev. 7");
+    assert!(
+        version
+            > Version {
+                major: 4,
+                minor: 0,
+                build: 9,
+                revision: 9,
+            }
+    );
+    assert_eq!(stub.take_log(), ["version"]);
+}
+
+#[test]
+fn gets_and_sets_typed_settings() {
+    let stub = Stub::new();
+    let binds = stub.binds();
+
+    assert_eq!(binds.driver_setting(settings::MAX_VOICES).unwrap(), 0);
+    binds
+        .set_driver_setting(settings::MAX_VOICES, 1000)
+        .unwrap();
+    binds
+        .set_driver_setting(settings::IGNORE_SYSEX, true)
+        .unwrap();
+    assert_eq!(binds.driver_setting(settings::MAX_VOICES).unwrap(), 1000);
+    assert!(binds.driver_setting(settings::IGNORE_SYSEX).unwrap());
+    assert!(!binds.driver_setting(settings::MT32_MODE).unwrap());
+    assert_eq!(
+        stub.take_log(),
+        [
+            "get_setting 10026",
+            "set_setting 10026 1000",
+            "set_setting 10008 1",
+            "get_setting 10026",
+            "get_setting 10008",
+            "get_setting 10011",
+        ]
+    );
+
+    // The stub rejects codes it doesn't know
+    assert!(matches!(
+        binds.driver_setting(Setting::<u32>::new(0x20000)),
+        Err(KdmapiError::CallFailed("DriverSettings"))
+    ));
+}
+
+#[test]
+fn copies_the_debug_info() {
+    let stub = Stub::new();
+    let binds = stub.binds();
+
+    let info = binds.driver_debug_info().unwrap();
+    assert_eq!(info.rendering_time, 12.5);
+    assert_eq!(info.active_voices[0], 8);
+    assert_eq!(info.active_voices[9], 3);
+    assert_eq!(info.total_voices(), 11);
+    assert_eq!(info.asio_output_latency, 5.0);
+    assert_eq!(stub.take_log(), ["debug_info"]);
+}
+
+#[test]
+fn reports_missing_exports() {
+    let stub = Stub::new();
+    let mut vtable = *stub.binds().vtable();
+    vtable.return_kdmapi_ver = None;
+    vtable.driver_settings = None;
+    vtable.get_driver_debug_info = None;
+    let binds = unsafe { KDMAPIBinds::from_vtable(vtable) };
+
+    assert!(matches!(
+        binds.kdmapi_version(),
+        Err(KdmapiError::Unsupported("ReturnKDMAPIVer"))
+    ));
+    assert!(matches!(
+        binds.set_driver_setting(settings::ENABLE_SFX, false),
+        Err(KdmapiError::Unsupported("DriverSettings"))
+    ));
+    assert!(matches!(
+        binds.driver_debug_info(),
+        Err(KdmapiError::Unsupported("GetDriverDebugInfo"))
+    ));
+    assert!(stub.take_log().is_empty());
+}
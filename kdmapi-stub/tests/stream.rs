@@ -0,0 +1,433 @@
+mod common;
+
+use common::{loader, stub_file_name, Stub};
+use kdmapi::{
+    soundfont::{SoundFontEntry, SoundFontList},
+    KdmapiError, KdmapiLoadError, KdmapiLoader, ShortMessage,
+};
+
+#[test]
+fn loads_through_the_loader() {
+    let stub = Stub::new();
+    let binds = stub.binds();
+
+    assert!(binds.is_kdmapi_available());
+    assert!(binds.vtable().send_direct_long_data.is_some());
+}
+
+#[test]
+fn reports_every_tried_path() {
+    let err = KdmapiLoader::new()
+        .no_env_var()
+        .search_exe_dir(false)
+        .fallback_names(vec!["kdmapi-missing-1", "kdmapi-missing-2"])
+        .load()
+        .unwrap_err();
+
+    match err {
+        KdmapiLoadError::LibraryNotFound(attempts) => {
+            let paths: Vec<_> = attempts.iter().map(|a| a.path.to_str().unwrap()).collect();
+            assert_eq!(paths, vec!["kdmapi-missing-1", "kdmapi-missing-2"]);
+        }
+        err => panic!("unexpected error: {}", err),
+    }
+}
+
+#[test]
+fn loads_from_an_exe_subdir() {
+    let exe_dir = std::env::current_exe()
+        .unwrap()
+        .parent()
+        .unwrap()
+        .to_path_buf();
+    let subdir = exe_dir.join(format!("kdmapi-subdir-{}", std::process::id()));
+    std::fs::create_dir_all(&subdir).unwrap();
+    std::fs::copy(
+        exe_dir.join(stub_file_name()),
+        subdir.join(stub_file_name()),
+    )
+    .unwrap();
+
+    let binds = KdmapiLoader::new()
+        .no_env_var()
+        .search_exe_dir(false)
+        .system_search(false)
+        .exe_subdir(subdir.file_name().unwrap())
+        .fallback_names(vec![stub_file_name()])
+        .load_binds();
+    assert!(binds.is_ok());
+    drop(binds);
+    let _ = std::fs::remove_dir_all(&subdir);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn reports_missing_symbols_by_name() {
+    let err = KdmapiLoader::new()
+        .no_env_var()
+        .search_exe_dir(false)
+        .fallback_names(vec!["libc.so.6"])
+        .load_binds()
+        .err()
+        .unwrap();
+
+    match err {
+        KdmapiLoadError::MissingSymbols(names) => {
+            assert!(names.contains(&"IsKDMAPIAvailable"));
+            assert!(names.contains(&"SendDirectDataNoBuf"));
+        }
+        err => panic!("unexpected error: {}", err),
+    }
+}
+
+#[test]
+fn sends_and_terminates() {
+    let stub = Stub::new();
+    let binds = stub.binds();
+
+    {
+        let stream = binds.open_stream().unwrap();
+        stream.send_direct_data(0x7F4090).unwrap();
+        stream
+            .send_no_buf(ShortMessage::note_off(0, 0x40, 0).unwrap())
+            .unwrap();
+        stream.reset();
+    }
+
+    assert_eq!(
+        stub.take_log(),
+        vec![
+            "init",
+            "short 7f4090",
+            "short_no_buf 004080",
+            "reset",
+            "terminate"
+        ]
+    );
+}
+
+#[test]
+fn rejects_a_second_exclusive_stream() {
+    let stub = Stub::new();
+    let binds = stub.binds();
+
+    let stream = binds.open_stream().unwrap();
+    assert!(matches!(
+        binds.clone().open_stream(),
+        Err(KdmapiError::StreamAlreadyOpen)
+    ));
+    assert!(matches!(
+        binds.open_shared_stream(),
+        Err(KdmapiError::StreamAlreadyOpen)
+    ));
+    drop(stream);
+
+    assert!(binds.open_stream().is_ok());
+}
+
+#[test]
+fn separately_loaded_bindings_share_the_stream() {
+    let stub = Stub::new();
+    let first_binds = stub.binds();
+    let second_binds = stub.binds();
+
+    let stream = first_binds.open_stream().unwrap();
+    assert!(matches!(
+        second_binds.open_stream(),
+        Err(KdmapiError::StreamAlreadyOpen)
+    ));
+    assert!(matches!(
+        second_binds.open_shared_stream(),
+        Err(KdmapiError::StreamAlreadyOpen)
+    ));
+    drop(stream);
+    assert_eq!(stub.take_log(), ["init", "terminate"]);
+
+    let first = first_binds.open_shared_stream().unwrap();
+    let second = second_binds.open_shared_stream().unwrap();
+    drop(first);
+    assert_eq!(stub.take_log(), ["init"]);
+    drop(second);
+    assert_eq!(stub.take_log(), ["terminate"]);
+}
+
+#[test]
+fn shared_streams_initialize_once() {
+    let stub = Stub::new();
+    let binds = stub.binds();
+
+    let first = binds.open_shared_stream().unwrap();
+    let second = binds.open_shared_stream().unwrap();
+    assert!(matches!(
+        binds.open_stream(),
+        Err(KdmapiError::StreamAlreadyOpen)
+    ));
+
+    drop(first);
+    assert_eq!(stub.take_log(), vec!["init"]);
+    drop(second);
+    assert_eq!(stub.take_log(), vec!["terminate"]);
+}
+
+#[test]
+fn reports_unavailable_kdmapi() {
+    let stub = Stub::new();
+    stub.set_available(false);
+
+    let result = stub.binds().open_stream();
+    assert!(matches!(result, Err(KdmapiError::Unavailable)));
+    assert!(stub.take_log().is_empty());
+}
+
+#[test]
+fn reports_init_failures() {
+    let stub = Stub::new();
+    stub.set_init_result(0);
+    let binds = stub.binds();
+
+    assert!(matches!(
+        binds.open_stream(),
+        Err(KdmapiError::InitFailed(0))
+    ));
+
+    // A failed init doesn't leave the stream marked as open
+    stub.set_init_result(1);
+    assert!(binds.open_stream().is_ok());
+}
+
+#[test]
+fn reports_send_failures() {
+    let stub = Stub::new();
+    let stream = stub.binds().open_stream().unwrap();
+    stub.set_send_result(6);
+
+    assert!(matches!(
+        stream.send_direct_data(0x90),
+        Err(KdmapiError::SendFailed(6))
+    ));
+}
+
+#[test]
+fn sends_sysex() {
+    let stub = Stub::new();
+    let stream = stub.binds().open_stream().unwrap();
+    stub.clear_log();
+
+    stream
+        .send_sysex(&[0xF0, 0x7E, 0x7F, 0x09, 0x01, 0xF7])
+        .unwrap();
+    assert!(matches!(
+        stream.send_sysex(&[0xF0, 0x90, 0xF7]),
+        Err(KdmapiError::InvalidSysEx)
+    ));
+    assert!(matches!(
+        stream.send_sysex(&[0x7E, 0xF7]),
+        Err(KdmapiError::InvalidSysEx)
+    ));
+
+    assert_eq!(stub.take_log(), vec!["sysex f07e7f0901f7"]);
+}
+
+#[test]
+fn loader_finds_the_stub_in_the_exe_dir() {
+    let _stub = Stub::new();
+    let candidates = loader().candidates();
+    assert!(candidates.iter().any(|path| path.exists()));
+}
+
+fn all_off_lines() -> Vec<String> {
+    (0..16)
+        .flat_map(|channel| {
+            vec![
+                format!("short_no_buf 0040b{:x}", channel),
+                format!("short_no_buf 0078b{:x}", channel),
+                format!("short_no_buf 007bb{:x}", channel),
+            ]
+        })
+        .collect()
+}
+
+#[test]
+fn tracked_notes_are_cut_on_drop() {
+    let stub = Stub::new();
+    let mut stream = stub.binds().open_stream().unwrap();
+    stream.set_note_tracking(true);
+
+    stream.send_direct_data(0x403C90).unwrap();
+    stream.send_direct_data(0x403C90).unwrap();
+    stream.send_direct_data_no_buf(0x7F4091).unwrap();
+    stream.send_direct_data(0x003C90).unwrap();
+    assert_eq!(stream.active_notes(), Some(vec![(0, 0x3C), (1, 0x40)]));
+    stub.clear_log();
+    drop(stream);
+
+    let mut expected = vec![
+        "short_no_buf 003c80".to_owned(),
+        "short_no_buf 004081".to_owned(),
+    ];
+    expected.extend(all_off_lines());
+    expected.push("terminate".to_owned());
+    assert_eq!(stub.take_log(), expected);
+}
+
+#[test]
+fn panic_without_tracking_sends_all_off() {
+    let stub = Stub::new();
+    let stream = stub.binds().open_stream().unwrap();
+    stream.send_direct_data(0x403C90).unwrap();
+    assert_eq!(stream.active_notes(), None);
+    stub.clear_log();
+
+    stream.panic().unwrap();
+    assert_eq!(stub.take_log(), all_off_lines());
+
+    // Untracked streams don't send anything extra on drop
+    drop(stream);
+    assert_eq!(stub.take_log(), vec!["terminate"]);
+}
+
+#[test]
+fn restores_a_snapshot_after_reset() {
+    let stub = Stub::new();
+    let mut stream = stub.binds().open_stream().unwrap();
+    stream.set_state_tracking(true);
+
+    // Volume, program with bank, pitch bend range of 12 semitones through
+    // RPN 0, and a pitch bend on channel 3
+    for data in [
+        0x6407B2, 0x0100B2, 0x2AC2, 0x0065B2, 0x0064B2, 0x0C06B2, 0x2000E2,
+    ] {
+        stream.send_direct_data(data).unwrap();
+    }
+    // An NRPN's data entry doesn't touch the pitch bend range
+    for data in [0x0163B2, 0x0062B2, 0x3006B2] {
+        stream.send_direct_data(data).unwrap();
+    }
+
+    let snapshot = stream.snapshot().unwrap();
+    let channel = snapshot.channel(2);
+    assert_eq!(channel.program, Some(0x2A));
+    assert_eq!(channel.bank(), (Some(1), None));
+    assert_eq!(channel.volume(), Some(0x64));
+    assert_eq!(channel.pitch_bend_range, Some((12, 0)));
+    assert_eq!(channel.pitch_bend, Some(0x1000));
+
+    stream.reset();
+    assert_eq!(stream.snapshot(), Some(kdmapi::MidiState::new()));
+    stub.clear_log();
+
+    stream.restore(&snapshot).unwrap();
+    assert_eq!(
+        stub.take_log(),
+        [
+            "short 0100b2",
+            "short 002ac2",
+            "short 0065b2",
+            "short 0064b2",
+            "short 0c06b2",
+            "short 0026b2",
+            "short 7f65b2",
+            "short 7f64b2",
+            "short 6407b2",
+            "short 2000e2",
+        ]
+    );
+    assert_eq!(stream.snapshot(), Some(snapshot));
+}
+
+#[test]
+fn sends_batches_in_order() {
+    let stub = Stub::new();
+    let mut stream = stub.binds().open_stream().unwrap();
+    stream.set_note_tracking(true);
+    stub.clear_log();
+
+    stream.send_batch(&[0x643C90, 0x644090, 0x003C80]).unwrap();
+    stream.send_batch_no_buf(&[0x644390]).unwrap();
+    assert_eq!(
+        stub.take_log(),
+        [
+            "short 643c90",
+            "short 644090",
+            "short 003c80",
+            "short_no_buf 644390"
+        ]
+    );
+    assert_eq!(stream.active_notes(), Some(vec![(0, 0x40), (0, 0x43)]));
+
+    // Stops at the failing message, and only what was sent is tracked
+    stub.fail_next_sends(1);
+    assert!(matches!(
+        stream.send_batch(&[0x644590, 0x644790]),
+        Err(KdmapiError::SendFailed(1))
+    ));
+    assert_eq!(stub.take_log(), ["short 644590"]);
+    assert_eq!(stream.active_notes(), Some(vec![(0, 0x40), (0, 0x43)]));
+}
+
+#[test]
+fn loads_soundfont_lists() {
+    let stub = Stub::new();
+    let binds = stub.binds();
+    let stream = binds.open_stream().unwrap();
+    let path = std::path::Path::new("/tmp/sound fonts/list.sflist");
+    stub.clear_log();
+
+    stream.load_soundfont_list(path).unwrap();
+    stub.set_soundfont_result(false);
+    assert!(matches!(
+        stream.load_soundfont_list(path),
+        Err(KdmapiError::SoundFontListRejected(rejected)) if rejected == path
+    ));
+    assert!(matches!(
+        stream.load_soundfont_list(std::path::Path::new("a\0b")),
+        Err(KdmapiError::InvalidPath(_))
+    ));
+    assert_eq!(
+        stub.take_log(),
+        [
+            "soundfonts /tmp/sound fonts/list.sflist",
+            "soundfonts /tmp/sound fonts/list.sflist"
+        ]
+    );
+    drop(stream);
+
+    // Bindings for a library without the export
+    let mut vtable = *binds.vtable();
+    vtable.load_custom_soundfonts_list = None;
+    let binds = unsafe { kdmapi::KDMAPIBinds::from_vtable(vtable) };
+    let stream = binds.open_stream().unwrap();
+    assert!(matches!(
+        stream.load_soundfont_list(path),
+        Err(KdmapiError::Unsupported("LoadCustomSoundFontsList"))
+    ));
+}
+
+#[test]
+fn loads_a_generated_soundfont_list() {
+    let stub = Stub::new();
+    let binds = stub.binds();
+    let stream = binds.open_stream().unwrap();
+    stub.clear_log();
+
+    let mut drums = SoundFontEntry::new("/sf/drums.sf2");
+    drums.source_bank = Some(128);
+    drums.xg_drums = true;
+    let list = SoundFontList {
+        entries: vec![SoundFontEntry::new("/sf/piano.sf2"), drums],
+    };
+    stream.load_soundfonts(&list).unwrap();
+
+    let log = stub.take_log();
+    let path = log[0].strip_prefix("soundfonts ").unwrap();
+    assert!(!std::path::Path::new(path).exists());
+    let written: Vec<_> = log[1..].iter().map(|line| line.trim()).collect();
+    let expected = list.to_text().unwrap();
+    assert_eq!(written, expected.lines().collect::<Vec<_>>());
+
+    stub.set_soundfont_result(false);
+    assert!(matches!(
+        stream.load_soundfonts(&list),
+        Err(KdmapiError::SoundFontListRejected(_))
+    ));
+}
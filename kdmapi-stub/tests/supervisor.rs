@@ -0,0 +1,72 @@
+mod common;
+
+use std::time::Duration;
+
+use common::Stub;
+use kdmapi::{KdmapiError, RecoveryOptions, SupervisedStream};
+
+fn options() -> RecoveryOptions {
+    RecoveryOptions {
+        max_attempts: 3,
+        initial_backoff: Duration::from_millis(1),
+        max_backoff: Duration::from_millis(2),
+    }
+}
+
+#[test]
+fn reopens_and_replays_state_after_a_failed_send() {
+    let stub = Stub::new();
+    let mut stream = SupervisedStream::open(&stub.binds(), options()).unwrap();
+    stream.send_direct_data(0x6407B0).unwrap();
+    stream.send_direct_data(0x05C0).unwrap();
+    stub.clear_log();
+
+    stub.fail_next_sends(1);
+    stream.send_direct_data(0x403C90).unwrap();
+
+    assert_eq!(
+        stub.take_log(),
+        [
+            "short 403c90",
+            "terminate",
+            "init",
+            "short 0005c0",
+            "short 6407b0",
+            "short 403c90",
+        ]
+    );
+    assert_eq!(stream.recoveries(), 1);
+    assert!(stream.is_open());
+}
+
+#[test]
+fn backs_off_until_initialization_succeeds_or_gives_up() {
+    let stub = Stub::new();
+    stub.set_init_result(0);
+
+    let err = SupervisedStream::open(&stub.binds(), options()).err();
+    assert!(matches!(err, Some(KdmapiError::InitFailed(0))));
+    assert_eq!(stub.take_log(), ["init", "init", "init"]);
+}
+
+#[test]
+fn health_check_reopens_once_kdmapi_is_back() {
+    let stub = Stub::new();
+    let mut stream = SupervisedStream::open(&stub.binds(), options()).unwrap();
+    stream.send_direct_data(0x2A0AB0).unwrap();
+    stream.check_health().unwrap();
+    assert_eq!(stream.recoveries(), 0);
+
+    stub.set_available(false);
+    assert!(matches!(
+        stream.check_health(),
+        Err(KdmapiError::Unavailable)
+    ));
+    assert!(!stream.is_open());
+    stub.clear_log();
+
+    stub.set_available(true);
+    stream.send_direct_data(0x403C90).unwrap();
+    assert_eq!(stub.take_log(), ["init", "short 2a0ab0", "short 403c90"]);
+    assert_eq!(stream.recoveries(), 1);
+}
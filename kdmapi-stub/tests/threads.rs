@@ -0,0 +1,105 @@
+mod common;
+
+use std::{sync::Arc, thread};
+
+use common::Stub;
+use kdmapi::{KDMAPIStream, SenderOptions, SenderThread, SharedStream};
+
+fn assert_send<T: Send>() {}
+fn assert_send_sync<T: Send + Sync>() {}
+
+#[test]
+fn threading_model() {
+    assert_send::<KDMAPIStream>();
+    assert_send_sync::<SharedStream>();
+    assert_send_sync::<kdmapi::KDMAPIBinds>();
+    assert_send_sync::<kdmapi::StreamSender>();
+}
+
+/// Checks that every producer's messages arrived, in order
+fn assert_all_sent(log: &[String], prefix: &str, producers: u32, count: u32) {
+    for producer in 0..producers {
+        let sent: Vec<_> = log
+            .iter()
+            .filter_map(|line| line.strip_prefix(prefix))
+            .map(|data| u32::from_str_radix(data, 16).unwrap())
+            .filter(|data| data & 0x0F == producer)
+            .map(|data| data >> 8)
+            .collect();
+        assert_eq!(sent, (0..count).collect::<Vec<_>>());
+    }
+}
+
+#[test]
+fn streams_can_be_moved_and_dropped_on_other_threads() {
+    let stub = Stub::new();
+    let stream = stub.binds().open_stream().unwrap();
+    thread::spawn(move || {
+        stream.send_direct_data(0x643C90).unwrap();
+    })
+    .join()
+    .unwrap();
+
+    assert_eq!(stub.take_log(), ["init", "short 643c90", "terminate"]);
+}
+
+#[test]
+fn shared_stream_serializes_concurrent_senders() {
+    let stub = Stub::new();
+    let stream = Arc::new(SharedStream::new(stub.binds().open_stream().unwrap()));
+    stub.clear_log();
+
+    let threads: Vec<_> = (0..8u32)
+        .map(|producer| {
+            let stream = stream.clone();
+            thread::spawn(move || {
+                for i in 0..500u32 {
+                    let data = 0x90 | producer | i << 8;
+                    if i % 2 == 0 {
+                        stream.send_direct_data(data).unwrap();
+                    } else {
+                        stream.send_batch(&[data]).unwrap();
+                    }
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    let log = stub.take_log();
+    assert_eq!(log.len(), 4000);
+    assert_all_sent(&log, "short ", 8, 500);
+
+    drop(Arc::try_unwrap(stream).ok().unwrap().into_inner());
+    assert_eq!(stub.take_log(), ["terminate"]);
+}
+
+#[test]
+fn sender_thread_feeds_the_stream_from_many_threads() {
+    let stub = Stub::new();
+    let stream = stub.binds().open_stream().unwrap();
+    stub.clear_log();
+    let sender_thread = SenderThread::spawn(stream, SenderOptions::default());
+
+    let threads: Vec<_> = (0..8u32)
+        .map(|producer| {
+            let sender = sender_thread.sender();
+            thread::spawn(move || {
+                for i in 0..500u32 {
+                    sender.send(0x90 | producer | i << 8).unwrap();
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    drop(sender_thread.into_sink());
+
+    let mut log = stub.take_log();
+    assert_eq!(log.pop().as_deref(), Some("terminate"));
+    assert_eq!(log.len(), 4000);
+    assert_all_sent(&log, "short ", 8, 500);
+}
@@ -0,0 +1,312 @@
+use std::{
+    collections::HashMap,
+    os::raw::c_void,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, Weak,
+    },
+    thread,
+};
+
+use lazy_static::lazy_static;
+use libloading::Library;
+
+use crate::{
+    driver::{OM_GET, OM_SET},
+    settings::{Setting, SettingValue},
+    DriverDebugInfo, KDMAPIStream, KdmapiError, KdmapiLoadError, KdmapiLoader, KdmapiVTable,
+    Version,
+};
+
+// Values of the stream state. Anything between `STREAM_CLOSED` and
+// `STREAM_TRANSITIONING` is the number of open shared streams.
+const STREAM_CLOSED: usize = 0;
+const STREAM_EXCLUSIVE: usize = usize::MAX;
+const STREAM_TRANSITIONING: usize = usize::MAX - 1;
+
+lazy_static! {
+    /// The stream state of every KDMAPI implementation that has bindings,
+    /// keyed by the address of its `InitializeKDMAPIStream`. Loading the
+    /// same library twice maps it at the same address, so independently
+    /// loaded bindings share one state, like the OmniMIDI stream they
+    /// control.
+    static ref STREAM_STATES: Mutex<HashMap<usize, Weak<AtomicUsize>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Returns the stream state shared by all bindings to the implementation
+/// in `vtable`
+fn shared_stream_state(vtable: &KdmapiVTable) -> Arc<AtomicUsize> {
+    let key = vtable.initialize_kdmapi_stream as usize;
+    let mut states = STREAM_STATES.lock().unwrap_or_else(|err| err.into_inner());
+    if let Some(state) = states.get(&key).and_then(Weak::upgrade) {
+        return state;
+    }
+
+    // Forget libraries whose bindings are all gone
+    states.retain(|_, state| state.strong_count() > 0);
+    let state = Arc::new(AtomicUsize::new(STREAM_CLOSED));
+    states.insert(key, Arc::downgrade(&state));
+    state
+}
+
+/// The dynamic bindings for KDMAPI
+///
+/// Owns a reference to the loaded library (if any), so it can be cloned and
+/// moved freely. The library is unloaded once the last clone (and every
+/// stream opened from it) is dropped.
+///
+/// OmniMIDI has a single stream per process, so all bindings to the same
+/// library share which streams are open, even when they were loaded
+/// separately (e.g. [`KDMAPI`](crate::KDMAPI) and [`KDMAPIBinds::load`]).
+#[derive(Clone)]
+pub struct KDMAPIBinds {
+    _lib: Option<Arc<Library>>,
+    pub(crate) vtable: KdmapiVTable,
+    stream_state: Arc<AtomicUsize>,
+}
+
+impl KDMAPIBinds {
+    /// Loads the KDMAPI library from the default [`KdmapiLoader`] search paths
+    pub fn load() -> Result<Self, KdmapiLoadError> {
+        KdmapiLoader::new().load_binds()
+    }
+
+    /// Resolves the KDMAPI exports from an already loaded library
+    pub fn from_library(lib: impl Into<Arc<Library>>) -> Result<Self, KdmapiLoadError> {
+        let lib = lib.into();
+        let vtable = KdmapiVTable::from_library(&lib)?;
+        Ok(KDMAPIBinds {
+            _lib: Some(lib),
+            stream_state: shared_stream_state(&vtable),
+            vtable,
+        })
+    }
+
+    /// Creates bindings that call the given function pointers instead of a
+    /// dynamically loaded library, e.g. a statically linked OmniMIDI or a
+    /// fake implementation for tests.
+    ///
+    /// # Safety
+    ///
+    /// Every function in `vtable` must behave like the KDMAPI export it
+    /// stands in for, and must stay callable for as long as these bindings
+    /// (or any stream opened from them) exist.
+    ///
+    /// Bindings whose `initialize_kdmapi_stream` is the same function share
+    /// their stream state with each other and with loaded bindings.
+    pub unsafe fn from_vtable(vtable: KdmapiVTable) -> Self {
+        KDMAPIBinds {
+            _lib: None,
+            stream_state: shared_stream_state(&vtable),
+            vtable,
+        }
+    }
+
+    /// Returns the function pointers these bindings call
+    pub fn vtable(&self) -> &KdmapiVTable {
+        &self.vtable
+    }
+
+    /// Calls `IsKDMAPIAvailable`
+    pub fn is_kdmapi_available(&self) -> bool {
+        unsafe { (self.vtable.is_kdmapi_available)() }
+    }
+
+    /// Calls `ReturnKDMAPIVer`
+    pub fn kdmapi_version(&self) -> Result<Version, KdmapiError> {
+        let return_ver = self
+            .vtable
+            .return_kdmapi_ver
+            .ok_or(KdmapiError::Unsupported("ReturnKDMAPIVer"))?;
+        let (mut major, mut minor, mut build, mut revision) = (0, 0, 0, 0);
+        if !unsafe { return_ver(&mut major, &mut minor, &mut build, &mut revision) } {
+            return Err(KdmapiError::CallFailed("ReturnKDMAPIVer"));
+        }
+        Ok(Version {
+            major,
+            minor,
+            build,
+            revision,
+        })
+    }
+
+    /// Reads a driver setting with `DriverSettings`
+    pub fn driver_setting<T: SettingValue>(&self, setting: Setting<T>) -> Result<T, KdmapiError> {
+        let mut raw = 0;
+        self.call_driver_settings(setting.code(), OM_GET, &mut raw)?;
+        Ok(T::from_raw(raw))
+    }
+
+    /// Changes a driver setting with `DriverSettings`. Some settings only
+    /// take effect once the stream is reopened.
+    pub fn set_driver_setting<T: SettingValue>(
+        &self,
+        setting: Setting<T>,
+        value: T,
+    ) -> Result<(), KdmapiError> {
+        let mut raw = value.to_raw();
+        self.call_driver_settings(setting.code(), OM_SET, &mut raw)
+    }
+
+    fn call_driver_settings(&self, code: u32, mode: u32, raw: &mut u32) -> Result<(), KdmapiError> {
+        let driver_settings = self
+            .vtable
+            .driver_settings
+            .ok_or(KdmapiError::Unsupported("DriverSettings"))?;
+        let value = raw as *mut u32 as *mut c_void;
+        if unsafe { driver_settings(code, mode, value, std::mem::size_of::<u32>() as u32) } {
+            Ok(())
+        } else {
+            Err(KdmapiError::CallFailed("DriverSettings"))
+        }
+    }
+
+    /// Returns a copy of the driver's debug info from `GetDriverDebugInfo`
+    pub fn driver_debug_info(&self) -> Result<DriverDebugInfo, KdmapiError> {
+        let get_debug_info = self
+            .vtable
+            .get_driver_debug_info
+            .ok_or(KdmapiError::Unsupported("GetDriverDebugInfo"))?;
+        let info = unsafe { get_debug_info() };
+        if info.is_null() {
+            return Err(KdmapiError::CallFailed("GetDriverDebugInfo"));
+        }
+        // OmniMIDI updates the struct from its own threads
+        Ok(unsafe { std::ptr::read_volatile(info) })
+    }
+
+    /// Calls `InitializeKDMAPIStream` and returns a stream struct with access
+    /// to the stream functions.
+    ///
+    /// Automatically calls `TerminateKDMAPIStream` when dropped.
+    ///
+    /// Errors with [`KdmapiError::StreamAlreadyOpen`] if another stream
+    /// (exclusive or shared) is already open from any bindings to the same
+    /// library.
+    pub fn open_stream(&self) -> Result<KDMAPIStream, KdmapiError> {
+        if self
+            .stream_state
+            .compare_exchange(
+                STREAM_CLOSED,
+                STREAM_EXCLUSIVE,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            )
+            .is_err()
+        {
+            return Err(KdmapiError::StreamAlreadyOpen);
+        }
+
+        if let Err(err) = self.initialize_stream() {
+            self.stream_state.store(STREAM_CLOSED, Ordering::Release);
+            return Err(err);
+        }
+        Ok(KDMAPIStream::new(self.clone(), false))
+    }
+
+    /// Opens a stream that can coexist with other shared streams.
+    ///
+    /// The first shared stream calls `InitializeKDMAPIStream`, and the last one
+    /// to be dropped calls `TerminateKDMAPIStream`. All of them send to the
+    /// same underlying KDMAPI stream.
+    ///
+    /// Errors if an exclusive stream from [`open_stream`](Self::open_stream)
+    /// is open.
+    pub fn open_shared_stream(&self) -> Result<KDMAPIStream, KdmapiError> {
+        loop {
+            match self.stream_state.load(Ordering::Acquire) {
+                STREAM_EXCLUSIVE => return Err(KdmapiError::StreamAlreadyOpen),
+                STREAM_TRANSITIONING => thread::yield_now(),
+                STREAM_CLOSED => {
+                    if self
+                        .stream_state
+                        .compare_exchange(
+                            STREAM_CLOSED,
+                            STREAM_TRANSITIONING,
+                            Ordering::AcqRel,
+                            Ordering::Acquire,
+                        )
+                        .is_err()
+                    {
+                        continue;
+                    }
+
+                    if let Err(err) = self.initialize_stream() {
+                        self.stream_state.store(STREAM_CLOSED, Ordering::Release);
+                        return Err(err);
+                    }
+                    self.stream_state.store(1, Ordering::Release);
+                    return Ok(KDMAPIStream::new(self.clone(), true));
+                }
+                count => {
+                    if self
+                        .stream_state
+                        .compare_exchange(count, count + 1, Ordering::AcqRel, Ordering::Acquire)
+                        .is_ok()
+                    {
+                        return Ok(KDMAPIStream::new(self.clone(), true));
+                    }
+                }
+            }
+        }
+    }
+
+    fn initialize_stream(&self) -> Result<(), KdmapiError> {
+        if !self.is_kdmapi_available() {
+            return Err(KdmapiError::Unavailable);
+        }
+
+        let result = unsafe { (self.vtable.initialize_kdmapi_stream)() };
+        if result == 0 {
+            Err(KdmapiError::InitFailed(result))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Releases a stream opened from these bindings, calling
+    /// `TerminateKDMAPIStream` if it was the last one.
+    pub(crate) fn close_stream(&self, shared: bool) {
+        if !shared {
+            unsafe {
+                (self.vtable.terminate_kdmapi_stream)();
+            }
+            self.stream_state.store(STREAM_CLOSED, Ordering::Release);
+            return;
+        }
+
+        loop {
+            match self.stream_state.load(Ordering::Acquire) {
+                1 => {
+                    if self
+                        .stream_state
+                        .compare_exchange(
+                            1,
+                            STREAM_TRANSITIONING,
+                            Ordering::AcqRel,
+                            Ordering::Acquire,
+                        )
+                        .is_ok()
+                    {
+                        unsafe {
+                            (self.vtable.terminate_kdmapi_stream)();
+                        }
+                        self.stream_state.store(STREAM_CLOSED, Ordering::Release);
+                        return;
+                    }
+                }
+                STREAM_TRANSITIONING => thread::yield_now(),
+                count => {
+                    if self
+                        .stream_state
+                        .compare_exchange(count, count - 1, Ordering::AcqRel, Ordering::Acquire)
+                        .is_ok()
+                    {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+/// How long before an event the timing threads stop sleeping and start
+/// spinning, to make up for the OS scheduler's sleep granularity
+pub(crate) const SPIN_THRESHOLD: Duration = Duration::from_millis(1);
+
+/// How often threads waiting on a [`VirtualClock`] check whether it has
+/// been advanced
+const VIRTUAL_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// The time source of a [`Scheduler`](crate::Scheduler),
+/// [`MidiPlayer`](crate::MidiPlayer) or [`RecordingSink`](crate::RecordingSink).
+///
+/// [`SystemClock`] is the real time. [`VirtualClock`] only moves when told
+/// to, for deterministic tests.
+pub trait Clock: Clone + Send + 'static {
+    /// Returns the current time
+    fn now(&self) -> Instant;
+
+    /// Blocks on `cond` until `deadline`, or until it's notified.
+    ///
+    /// May return early, so callers should check the time again.
+    fn wait_until<'a, T>(
+        &self,
+        cond: &Condvar,
+        guard: MutexGuard<'a, T>,
+        deadline: Instant,
+    ) -> MutexGuard<'a, T>;
+
+    /// How long before a deadline waiting should switch to spinning on
+    /// [`now`](Self::now), with the lock released
+    fn spin_threshold(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+/// The real time, from [`Instant::now`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn wait_until<'a, T>(
+        &self,
+        cond: &Condvar,
+        guard: MutexGuard<'a, T>,
+        deadline: Instant,
+    ) -> MutexGuard<'a, T> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        cond.wait_timeout(guard, timeout)
+            .unwrap_or_else(|err| err.into_inner())
+            .0
+    }
+
+    fn spin_threshold(&self) -> Duration {
+        SPIN_THRESHOLD
+    }
+}
+
+/// A clock that stands still until [`advance`](Self::advance) is called.
+///
+/// Clones share the same time, so a test can keep one and hand another to
+/// the code under test. Threads waiting on it notice an advance within a
+/// millisecond of real time.
+#[derive(Debug, Clone)]
+pub struct VirtualClock {
+    now: Arc<Mutex<Instant>>,
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VirtualClock {
+    /// Creates a clock stopped at the current real time
+    pub fn new() -> Self {
+        Self::starting_at(Instant::now())
+    }
+
+    /// Creates a clock stopped at `start`
+    pub fn starting_at(start: Instant) -> Self {
+        VirtualClock {
+            now: Arc::new(Mutex::new(start)),
+        }
+    }
+
+    /// Moves the time forward by `duration`
+    pub fn advance(&self, duration: Duration) {
+        *self.lock() += duration;
+    }
+
+    /// Moves the time to `instant`, if that's later than the current time
+    pub fn advance_to(&self, instant: Instant) {
+        let mut now = self.lock();
+        *now = (*now).max(instant);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Instant> {
+        self.now.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Instant {
+        *self.lock()
+    }
+
+    fn wait_until<'a, T>(
+        &self,
+        cond: &Condvar,
+        guard: MutexGuard<'a, T>,
+        deadline: Instant,
+    ) -> MutexGuard<'a, T> {
+        if self.now() >= deadline {
+            return guard;
+        }
+        cond.wait_timeout(guard, VIRTUAL_POLL_INTERVAL)
+            .unwrap_or_else(|err| err.into_inner())
+            .0
+    }
+}
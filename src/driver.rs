@@ -0,0 +1,50 @@
+use std::fmt;
+
+/// `OM_SET`, the `DriverSettings` mode for changing a setting
+pub(crate) const OM_SET: u32 = 0;
+/// `OM_GET`, the `DriverSettings` mode for reading a setting
+pub(crate) const OM_GET: u32 = 1;
+
+/// The KDMAPI version reported by `ReturnKDMAPIVer`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub build: u32,
+    pub revision: u32,
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}.{}.{} Rev. {}",
+            self.major, self.minor, self.build, self.revision
+        )
+    }
+}
+
+/// Layout-compatible with the start of OmniMIDI's `DebugInfo` struct, as
+/// returned by `GetDriverDebugInfo`.
+///
+/// Newer OmniMIDI versions append more fields, which aren't read.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DriverDebugInfo {
+    /// `RenderingTime`, the share of the audio buffer time spent rendering,
+    /// in percent
+    pub rendering_time: f32,
+    /// `ActiveVoices`, the voices playing on each channel
+    pub active_voices: [u32; 16],
+    /// `ASIOInputLatency`, in milliseconds
+    pub asio_input_latency: f64,
+    /// `ASIOOutputLatency`, in milliseconds
+    pub asio_output_latency: f64,
+}
+
+impl DriverDebugInfo {
+    /// Returns the voices playing on all channels together
+    pub fn total_voices(&self) -> u32 {
+        self.active_voices.iter().sum()
+    }
+}
@@ -0,0 +1,127 @@
+use std::{fmt, io, path::PathBuf};
+
+/// A single path that was tried while loading the KDMAPI library
+#[derive(Debug)]
+pub struct LoadAttempt {
+    /// The path (or bare library name) that was passed to the system loader
+    pub path: PathBuf,
+    /// Why loading from this path failed
+    pub error: libloading::Error,
+}
+
+/// Errors that can occur while loading the KDMAPI library and its exports
+#[derive(Debug)]
+pub enum KdmapiLoadError {
+    /// The KDMAPI library could not be found or opened at any of the tried paths
+    LibraryNotFound(Vec<LoadAttempt>),
+    /// The library was opened, but is missing the listed exports
+    MissingSymbols(Vec<&'static str>),
+    /// KDMAPI is not available on the current platform
+    UnsupportedPlatform,
+}
+
+impl fmt::Display for KdmapiLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KdmapiLoadError::LibraryNotFound(attempts) => {
+                write!(f, "failed to load the KDMAPI library")?;
+                if attempts.is_empty() {
+                    return write!(f, ": no paths to try");
+                }
+                write!(f, ", tried:")?;
+                for attempt in attempts {
+                    write!(f, "\n  {}: {}", attempt.path.display(), attempt.error)?;
+                }
+                Ok(())
+            }
+            KdmapiLoadError::MissingSymbols(names) => {
+                write!(f, "KDMAPI library is missing exports: {}", names.join(", "))
+            }
+            KdmapiLoadError::UnsupportedPlatform => {
+                write!(f, "KDMAPI is not supported on this platform")
+            }
+        }
+    }
+}
+
+impl std::error::Error for KdmapiLoadError {}
+
+/// Errors returned by [`KDMAPIBinds`](crate::KDMAPIBinds) and
+/// [`KDMAPIStream`](crate::KDMAPIStream)
+#[derive(Debug)]
+pub enum KdmapiError {
+    /// The KDMAPI library couldn't be loaded
+    Load(KdmapiLoadError),
+    /// `IsKDMAPIAvailable` returned false
+    Unavailable,
+    /// `InitializeKDMAPIStream` failed with the given return code
+    InitFailed(i32),
+    /// A stream is already open from the same bindings
+    StreamAlreadyOpen,
+    /// Sending data failed with the given return code
+    SendFailed(u32),
+    /// The loaded KDMAPI library doesn't export the named function
+    Unsupported(&'static str),
+    /// SysEx data that doesn't start with `F0` and end with `F7`, or that
+    /// contains other status bytes
+    InvalidSysEx,
+    /// A path that can't be passed to KDMAPI, because it contains a NUL
+    /// character or (on platforms other than Windows) isn't valid UTF-8
+    InvalidPath(PathBuf),
+    /// `LoadCustomSoundFontsList` returned false for the given list
+    SoundFontListRejected(PathBuf),
+    /// Writing a temporary file for KDMAPI failed
+    Io(io::Error),
+    /// The named KDMAPI function reported a failure
+    CallFailed(&'static str),
+}
+
+impl fmt::Display for KdmapiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KdmapiError::Load(err) => err.fmt(f),
+            KdmapiError::Unavailable => write!(f, "KDMAPI is not available"),
+            KdmapiError::InitFailed(code) => {
+                write!(f, "failed to initialize KDMAPI stream (code {})", code)
+            }
+            KdmapiError::StreamAlreadyOpen => write!(f, "KDMAPI stream is already open"),
+            KdmapiError::SendFailed(code) => {
+                write!(f, "failed to send data to KDMAPI (code {})", code)
+            }
+            KdmapiError::Unsupported(name) => {
+                write!(f, "the loaded KDMAPI library doesn't export {}", name)
+            }
+            KdmapiError::InvalidSysEx => write!(f, "invalid SysEx framing"),
+            KdmapiError::InvalidPath(path) => {
+                write!(f, "path can't be passed to KDMAPI: {}", path.display())
+            }
+            KdmapiError::SoundFontListRejected(path) => {
+                write!(f, "KDMAPI failed to load soundfont list {}", path.display())
+            }
+            KdmapiError::Io(err) => write!(f, "failed to write file for KDMAPI: {}", err),
+            KdmapiError::CallFailed(name) => write!(f, "KDMAPI's {} failed", name),
+        }
+    }
+}
+
+impl std::error::Error for KdmapiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            KdmapiError::Load(err) => Some(err),
+            KdmapiError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for KdmapiError {
+    fn from(err: io::Error) -> Self {
+        KdmapiError::Io(err)
+    }
+}
+
+impl From<KdmapiLoadError> for KdmapiError {
+    fn from(err: KdmapiLoadError) -> Self {
+        KdmapiError::Load(err)
+    }
+}
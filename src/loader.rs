@@ -0,0 +1,198 @@
+use std::{
+    env,
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use libloading::Library;
+
+use crate::{KDMAPIBinds, KdmapiLoadError, LoadAttempt};
+
+/// The environment variable checked by default for an explicit library path
+pub const DEFAULT_ENV_VAR: &str = "KDMAPI_LIBRARY";
+
+#[cfg(target_os = "windows")]
+const DEFAULT_NAMES: &[&str] = &["OmniMIDI\\OmniMIDI", "OmniMIDI"];
+
+#[cfg(target_os = "linux")]
+const DEFAULT_NAMES: &[&str] = &["libOmniMIDI.so"];
+
+#[cfg(target_os = "macos")]
+const DEFAULT_NAMES: &[&str] = &["libOmniMIDI.dylib"];
+
+#[cfg(not(any(target_os = "windows", target_os = "linux", target_os = "macos")))]
+const DEFAULT_NAMES: &[&str] = &[];
+
+/// Builder that decides where the KDMAPI library is loaded from.
+///
+/// Candidates are tried in this order, and the first one that loads wins:
+///
+/// 1. Paths added with [`path`](Self::path)
+/// 2. The path in the environment variable ([`DEFAULT_ENV_VAR`] by default)
+/// 3. Every fallback name inside the executable's directory and its
+///    subdirectories added with [`exe_subdir`](Self::exe_subdir)
+/// 4. Every fallback name inside the directories added with
+///    [`search_dir`](Self::search_dir)
+/// 5. The bare fallback names, resolved by the system's library search path
+///
+/// ```no_run
+/// use kdmapi::KdmapiLoader;
+///
+/// let binds = KdmapiLoader::new()
+///     .exe_subdir("lib")
+///     .load_binds()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct KdmapiLoader {
+    paths: Vec<PathBuf>,
+    env_var: Option<OsString>,
+    search_exe_dir: bool,
+    exe_subdirs: Vec<PathBuf>,
+    search_dirs: Vec<PathBuf>,
+    names: Vec<OsString>,
+    system_search: bool,
+}
+
+impl Default for KdmapiLoader {
+    fn default() -> Self {
+        KdmapiLoader {
+            paths: Vec::new(),
+            env_var: Some(DEFAULT_ENV_VAR.into()),
+            search_exe_dir: true,
+            exe_subdirs: Vec::new(),
+            search_dirs: Vec::new(),
+            names: DEFAULT_NAMES.iter().map(OsString::from).collect(),
+            system_search: true,
+        }
+    }
+}
+
+impl KdmapiLoader {
+    /// Creates a loader with the default search paths for the current platform
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an explicit library path, tried before anything else
+    pub fn path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.paths.push(path.into());
+        self
+    }
+
+    /// Sets the environment variable that can hold an explicit library path
+    pub fn env_var(mut self, name: impl Into<OsString>) -> Self {
+        self.env_var = Some(name.into());
+        self
+    }
+
+    /// Ignores the environment variable override
+    pub fn no_env_var(mut self) -> Self {
+        self.env_var = None;
+        self
+    }
+
+    /// Sets whether the executable's directory is searched (enabled by default)
+    pub fn search_exe_dir(mut self, search: bool) -> Self {
+        self.search_exe_dir = search;
+        self
+    }
+
+    /// Adds a directory relative to the executable's directory to search, e.g. `lib`
+    pub fn exe_subdir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.exe_subdirs.push(dir.into());
+        self
+    }
+
+    /// Adds a directory to search for the fallback names
+    pub fn search_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.search_dirs.push(dir.into());
+        self
+    }
+
+    /// Appends a library file name to the fallback names
+    pub fn fallback_name(mut self, name: impl Into<OsString>) -> Self {
+        self.names.push(name.into());
+        self
+    }
+
+    /// Replaces the fallback names, including the platform defaults
+    pub fn fallback_names<I>(mut self, names: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<OsString>,
+    {
+        self.names = names.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets whether the bare fallback names are passed to the system loader
+    /// (enabled by default)
+    pub fn system_search(mut self, search: bool) -> Self {
+        self.system_search = search;
+        self
+    }
+
+    /// Returns every path that [`load`](Self::load) would try, in order
+    pub fn candidates(&self) -> Vec<PathBuf> {
+        let mut candidates = self.paths.clone();
+
+        if let Some(var) = &self.env_var {
+            if let Some(path) = env::var_os(var).filter(|path| !path.is_empty()) {
+                candidates.push(path.into());
+            }
+        }
+
+        let mut dirs = Vec::new();
+        if self.search_exe_dir || !self.exe_subdirs.is_empty() {
+            if let Some(exe_dir) = exe_dir() {
+                if self.search_exe_dir {
+                    dirs.push(exe_dir.clone());
+                }
+                dirs.extend(self.exe_subdirs.iter().map(|dir| exe_dir.join(dir)));
+            }
+        }
+        dirs.extend(self.search_dirs.iter().cloned());
+
+        for dir in dirs {
+            candidates.extend(self.names.iter().map(|name| dir.join(name)));
+        }
+
+        if self.system_search {
+            candidates.extend(self.names.iter().map(PathBuf::from));
+        }
+
+        candidates
+    }
+
+    /// Loads the first candidate library that opens successfully.
+    ///
+    /// Errors with every attempted path if none of them could be loaded.
+    pub fn load(&self) -> Result<Library, KdmapiLoadError> {
+        let candidates = self.candidates();
+        if candidates.is_empty() && DEFAULT_NAMES.is_empty() {
+            return Err(KdmapiLoadError::UnsupportedPlatform);
+        }
+
+        let mut attempts = Vec::new();
+        for path in candidates {
+            match unsafe { Library::new(&path) } {
+                Ok(lib) => return Ok(lib),
+                Err(error) => attempts.push(LoadAttempt { path, error }),
+            }
+        }
+
+        Err(KdmapiLoadError::LibraryNotFound(attempts))
+    }
+
+    /// Loads the library and resolves the KDMAPI exports from it
+    pub fn load_binds(&self) -> Result<KDMAPIBinds, KdmapiLoadError> {
+        KDMAPIBinds::from_library(self.load()?)
+    }
+}
+
+fn exe_dir() -> Option<PathBuf> {
+    env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf))
+}
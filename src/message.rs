@@ -0,0 +1,319 @@
+use std::fmt;
+
+/// Errors from building or decoding a [`ShortMessage`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageError {
+    /// A channel outside of 0-15
+    InvalidChannel(u8),
+    /// A data value outside of 0-127
+    InvalidU7(u8),
+    /// A 14-bit value outside of 0-16383
+    InvalidU14(u16),
+    /// A packed message whose status byte isn't a supported short message
+    InvalidStatus(u8),
+}
+
+impl fmt::Display for MessageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageError::InvalidChannel(v) => write!(f, "invalid MIDI channel {}", v),
+            MessageError::InvalidU7(v) => write!(f, "invalid 7-bit value {}", v),
+            MessageError::InvalidU14(v) => write!(f, "invalid 14-bit value {}", v),
+            MessageError::InvalidStatus(v) => write!(f, "unsupported status byte {:#04X}", v),
+        }
+    }
+}
+
+impl std::error::Error for MessageError {}
+
+/// A MIDI channel, 0-15
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Channel(u8);
+
+impl Channel {
+    /// Errors if `channel` is above 15
+    pub fn new(channel: u8) -> Result<Self, MessageError> {
+        if channel < 16 {
+            Ok(Channel(channel))
+        } else {
+            Err(MessageError::InvalidChannel(channel))
+        }
+    }
+
+    /// Returns the channel number
+    pub fn get(self) -> u8 {
+        self.0
+    }
+}
+
+/// A 7-bit MIDI data value, 0-127
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct U7(u8);
+
+impl U7 {
+    /// Errors if `value` is above 127
+    pub fn new(value: u8) -> Result<Self, MessageError> {
+        if value < 0x80 {
+            Ok(U7(value))
+        } else {
+            Err(MessageError::InvalidU7(value))
+        }
+    }
+
+    /// Returns the value
+    pub fn get(self) -> u8 {
+        self.0
+    }
+}
+
+/// A 14-bit MIDI value, 0-16383
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct U14(u16);
+
+impl U14 {
+    /// The center value, e.g. no pitch bend
+    pub const CENTER: U14 = U14(0x2000);
+
+    /// Errors if `value` is above 16383
+    pub fn new(value: u16) -> Result<Self, MessageError> {
+        if value < 0x4000 {
+            Ok(U14(value))
+        } else {
+            Err(MessageError::InvalidU14(value))
+        }
+    }
+
+    /// Returns the value
+    pub fn get(self) -> u16 {
+        self.0
+    }
+}
+
+/// A MIDI short message, as sent with `SendDirectData`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ShortMessage {
+    /// Note off, status `0x8n`
+    NoteOff {
+        channel: Channel,
+        key: U7,
+        velocity: U7,
+    },
+    /// Note on, status `0x9n`
+    NoteOn {
+        channel: Channel,
+        key: U7,
+        velocity: U7,
+    },
+    /// Polyphonic key pressure, status `0xAn`
+    PolyPressure {
+        channel: Channel,
+        key: U7,
+        pressure: U7,
+    },
+    /// Control change, status `0xBn`
+    ControlChange {
+        channel: Channel,
+        controller: U7,
+        value: U7,
+    },
+    /// Program change, status `0xCn`
+    ProgramChange { channel: Channel, program: U7 },
+    /// Channel pressure, status `0xDn`
+    ChannelPressure { channel: Channel, pressure: U7 },
+    /// Pitch bend, status `0xEn`
+    PitchBend { channel: Channel, value: U14 },
+    /// Timing clock, `0xF8`
+    TimingClock,
+    /// Start, `0xFA`
+    Start,
+    /// Continue, `0xFB`
+    Continue,
+    /// Stop, `0xFC`
+    Stop,
+    /// Active sensing, `0xFE`
+    ActiveSensing,
+    /// System reset, `0xFF`
+    SystemReset,
+}
+
+impl ShortMessage {
+    /// Builds a note off message, validating the values
+    pub fn note_off(channel: u8, key: u8, velocity: u8) -> Result<Self, MessageError> {
+        Ok(ShortMessage::NoteOff {
+            channel: Channel::new(channel)?,
+            key: U7::new(key)?,
+            velocity: U7::new(velocity)?,
+        })
+    }
+
+    /// Builds a note on message, validating the values
+    pub fn note_on(channel: u8, key: u8, velocity: u8) -> Result<Self, MessageError> {
+        Ok(ShortMessage::NoteOn {
+            channel: Channel::new(channel)?,
+            key: U7::new(key)?,
+            velocity: U7::new(velocity)?,
+        })
+    }
+
+    /// Builds a polyphonic key pressure message, validating the values
+    pub fn poly_pressure(channel: u8, key: u8, pressure: u8) -> Result<Self, MessageError> {
+        Ok(ShortMessage::PolyPressure {
+            channel: Channel::new(channel)?,
+            key: U7::new(key)?,
+            pressure: U7::new(pressure)?,
+        })
+    }
+
+    /// Builds a control change message, validating the values
+    pub fn control_change(channel: u8, controller: u8, value: u8) -> Result<Self, MessageError> {
+        Ok(ShortMessage::ControlChange {
+            channel: Channel::new(channel)?,
+            controller: U7::new(controller)?,
+            value: U7::new(value)?,
+        })
+    }
+
+    /// Builds a program change message, validating the values
+    pub fn program_change(channel: u8, program: u8) -> Result<Self, MessageError> {
+        Ok(ShortMessage::ProgramChange {
+            channel: Channel::new(channel)?,
+            program: U7::new(program)?,
+        })
+    }
+
+    /// Builds a channel pressure message, validating the values
+    pub fn channel_pressure(channel: u8, pressure: u8) -> Result<Self, MessageError> {
+        Ok(ShortMessage::ChannelPressure {
+            channel: Channel::new(channel)?,
+            pressure: U7::new(pressure)?,
+        })
+    }
+
+    /// Builds a pitch bend message, validating the values. 8192 is the center.
+    pub fn pitch_bend(channel: u8, value: u16) -> Result<Self, MessageError> {
+        Ok(ShortMessage::PitchBend {
+            channel: Channel::new(channel)?,
+            value: U14::new(value)?,
+        })
+    }
+
+    /// Returns the channel of a channel message, or `None` for system
+    /// real-time messages
+    pub fn channel(&self) -> Option<Channel> {
+        match *self {
+            ShortMessage::NoteOff { channel, .. }
+            | ShortMessage::NoteOn { channel, .. }
+            | ShortMessage::PolyPressure { channel, .. }
+            | ShortMessage::ControlChange { channel, .. }
+            | ShortMessage::ProgramChange { channel, .. }
+            | ShortMessage::ChannelPressure { channel, .. }
+            | ShortMessage::PitchBend { channel, .. } => Some(channel),
+            _ => None,
+        }
+    }
+
+    /// Packs the message into the `u32` format used by `SendDirectData`
+    /// (status in the lowest byte, followed by the data bytes)
+    pub fn pack(&self) -> u32 {
+        let (status, data1, data2) = match *self {
+            ShortMessage::NoteOff {
+                channel,
+                key,
+                velocity,
+            } => (0x80 | channel.0, key.0, velocity.0),
+            ShortMessage::NoteOn {
+                channel,
+                key,
+                velocity,
+            } => (0x90 | channel.0, key.0, velocity.0),
+            ShortMessage::PolyPressure {
+                channel,
+                key,
+                pressure,
+            } => (0xA0 | channel.0, key.0, pressure.0),
+            ShortMessage::ControlChange {
+                channel,
+                controller,
+                value,
+            } => (0xB0 | channel.0, controller.0, value.0),
+            ShortMessage::ProgramChange { channel, program } => (0xC0 | channel.0, program.0, 0),
+            ShortMessage::ChannelPressure { channel, pressure } => {
+                (0xD0 | channel.0, pressure.0, 0)
+            }
+            ShortMessage::PitchBend { channel, value } => (
+                0xE0 | channel.0,
+                (value.0 & 0x7F) as u8,
+                (value.0 >> 7) as u8,
+            ),
+            ShortMessage::TimingClock => (0xF8, 0, 0),
+            ShortMessage::Start => (0xFA, 0, 0),
+            ShortMessage::Continue => (0xFB, 0, 0),
+            ShortMessage::Stop => (0xFC, 0, 0),
+            ShortMessage::ActiveSensing => (0xFE, 0, 0),
+            ShortMessage::SystemReset => (0xFF, 0, 0),
+        };
+        status as u32 | (data1 as u32) << 8 | (data2 as u32) << 16
+    }
+
+    /// Decodes a message packed in the `SendDirectData` format.
+    ///
+    /// Errors if the status byte isn't a channel or system real-time message,
+    /// or if a data byte used by the message has its high bit set.
+    pub fn unpack(data: u32) -> Result<Self, MessageError> {
+        let status = data as u8;
+        let data1 = (data >> 8) as u8;
+        let data2 = (data >> 16) as u8;
+        let channel = Channel(status & 0x0F);
+
+        let message = match status & 0xF0 {
+            0x80 => ShortMessage::NoteOff {
+                channel,
+                key: U7::new(data1)?,
+                velocity: U7::new(data2)?,
+            },
+            0x90 => ShortMessage::NoteOn {
+                channel,
+                key: U7::new(data1)?,
+                velocity: U7::new(data2)?,
+            },
+            0xA0 => ShortMessage::PolyPressure {
+                channel,
+                key: U7::new(data1)?,
+                pressure: U7::new(data2)?,
+            },
+            0xB0 => ShortMessage::ControlChange {
+                channel,
+                controller: U7::new(data1)?,
+                value: U7::new(data2)?,
+            },
+            0xC0 => ShortMessage::ProgramChange {
+                channel,
+                program: U7::new(data1)?,
+            },
+            0xD0 => ShortMessage::ChannelPressure {
+                channel,
+                pressure: U7::new(data1)?,
+            },
+            0xE0 => ShortMessage::PitchBend {
+                channel,
+                value: U14(U7::new(data1)?.0 as u16 | (U7::new(data2)?.0 as u16) << 7),
+            },
+            _ => match status {
+                0xF8 => ShortMessage::TimingClock,
+                0xFA => ShortMessage::Start,
+                0xFB => ShortMessage::Continue,
+                0xFC => ShortMessage::Stop,
+                0xFE => ShortMessage::ActiveSensing,
+                0xFF => ShortMessage::SystemReset,
+                _ => return Err(MessageError::InvalidStatus(status)),
+            },
+        };
+        Ok(message)
+    }
+}
+
+impl From<ShortMessage> for u32 {
+    fn from(message: ShortMessage) -> Self {
+        message.pack()
+    }
+}
@@ -0,0 +1,696 @@
+use std::{
+    ops::Range,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    smf::{EventKind, Smf, SmfError, TempoMap, TimedEvent},
+    tracker::all_off_messages,
+    validate_sysex, Clock, MidiEvent, MidiSink, MidiState, NoteTracker, SystemClock,
+};
+
+/// The slowest playback speed accepted by [`MidiPlayer::set_speed`]
+pub const MIN_SPEED: f64 = 0.5;
+/// The fastest playback speed accepted by [`MidiPlayer::set_speed`]
+pub const MAX_SPEED: f64 = 4.0;
+
+/// Where a [`MidiPlayer`] is in the song
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaybackState {
+    /// Not playing, at the start of the song
+    Stopped,
+    Playing,
+    Paused,
+    /// Every event has been sent
+    Finished,
+}
+
+struct PlayerEvent {
+    time: Duration,
+    event: MidiEvent,
+}
+
+struct Control {
+    state: PlaybackState,
+    /// The position while not playing
+    position: Duration,
+    /// When the start of the song was (or would have been) while playing,
+    /// in real time
+    origin: Instant,
+    speed: f64,
+    loop_range: Option<Range<Duration>>,
+    /// Index of the next event to send
+    next: usize,
+    /// Set when sounding notes should be cut
+    silence: bool,
+    /// Set after a seek, when sounding notes should be cut and the channel
+    /// state at the new position replayed
+    chase: bool,
+    /// Set while due events are being sent without the lock held
+    sending: bool,
+    quit: bool,
+}
+
+impl Control {
+    /// Returns the song position at `now`
+    fn position_at(&self, now: Instant) -> Duration {
+        match self.state {
+            PlaybackState::Playing => now
+                .saturating_duration_since(self.origin)
+                .mul_f64(self.speed),
+            _ => self.position,
+        }
+    }
+
+    /// Returns the real time at which the song reaches `position`
+    fn instant_at(&self, position: Duration) -> Instant {
+        self.origin + position.div_f64(self.speed)
+    }
+
+    /// Moves to `position`, keeping the song running if it's playing
+    fn seek(&mut self, events: &[PlayerEvent], position: Duration, now: Instant) {
+        self.position = position;
+        self.origin = now - position.div_f64(self.speed);
+        self.next = events.partition_point(|event| event.time < position);
+        self.chase = true;
+    }
+
+    /// Returns whether every event due at `now` has been sent
+    fn is_caught_up(&self, events: &[PlayerEvent], now: Instant) -> bool {
+        if self.silence || self.chase || self.sending {
+            return false;
+        }
+        if self.state != PlaybackState::Playing {
+            return true;
+        }
+        let next = events.get(self.next).map(|event| event.time);
+        let loop_end = self.loop_range.as_ref().map(|range| range.end);
+        match next.into_iter().chain(loop_end).min() {
+            Some(target) => target > self.position_at(now),
+            // About to be marked as finished
+            None => false,
+        }
+    }
+}
+
+struct Shared {
+    control: Mutex<Control>,
+    cond: Condvar,
+    /// Notified whenever the timing thread goes idle
+    idle: Condvar,
+    events: Vec<PlayerEvent>,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Control> {
+        self.control.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// Plays a [`Smf`] on a [`MidiSink`] such as a
+/// [`KDMAPIStream`](crate::KDMAPIStream).
+///
+/// Events are sent from a dedicated timing thread that owns the sink. Send
+/// errors for individual events are ignored so that one bad event doesn't
+/// stop playback.
+///
+/// Seeking cuts sounding notes and chases the [`MidiState`] at the new
+/// position, so the synth sounds as if it had played up to there.
+///
+/// Times are read from a [`Clock`], the real time by default. With a
+/// [`VirtualClock`](crate::VirtualClock), the song advances with the clock,
+/// and [`wait_due`](Self::wait_due) waits for the events up to there.
+pub struct MidiPlayer<S: MidiSink + Send + 'static, C: Clock = SystemClock> {
+    shared: Arc<Shared>,
+    clock: C,
+    duration: Duration,
+    tempo_map: TempoMap,
+    thread: Option<JoinHandle<S>>,
+}
+
+impl<S: MidiSink + Send + 'static> MidiPlayer<S> {
+    /// Merges the tracks of `smf` and starts the timing thread, stopped at
+    /// the start of the song.
+    pub fn new(sink: S, smf: &Smf) -> Self {
+        Self::with_clock(sink, smf, SystemClock)
+    }
+}
+
+impl<S: MidiSink + Send + 'static, C: Clock> MidiPlayer<S, C> {
+    /// Like [`new`](MidiPlayer::new), reading times from `clock`
+    pub fn with_clock(sink: S, smf: &Smf, clock: C) -> Self {
+        let events: Vec<_> = smf
+            .merged_events()
+            .into_iter()
+            .filter_map(|event| {
+                let midi = match event.kind {
+                    EventKind::Midi(data) => MidiEvent::Short(data),
+                    EventKind::SysEx(data) | EventKind::Escape(data) => {
+                        validate_sysex(&data).ok()?;
+                        MidiEvent::SysEx(data)
+                    }
+                    EventKind::Meta(_) => return None,
+                };
+                Some(PlayerEvent {
+                    time: event.time,
+                    event: midi,
+                })
+            })
+            .collect();
+        let duration = events.last().map(|e| e.time).unwrap_or_default();
+
+        let shared = Arc::new(Shared {
+            control: Mutex::new(Control {
+                state: PlaybackState::Stopped,
+                position: Duration::ZERO,
+                origin: clock.now(),
+                speed: 1.0,
+                loop_range: None,
+                next: 0,
+                silence: false,
+                chase: false,
+                sending: false,
+                quit: false,
+            }),
+            cond: Condvar::new(),
+            idle: Condvar::new(),
+            events,
+        });
+
+        let thread = {
+            let shared = shared.clone();
+            let clock = clock.clone();
+            thread::spawn(move || run(&shared, &clock, sink))
+        };
+
+        MidiPlayer {
+            shared,
+            clock,
+            duration,
+            tempo_map: smf.tempo_map(),
+            thread: Some(thread),
+        }
+    }
+
+    /// Starts or resumes playback. Restarts from the beginning if the song
+    /// has finished.
+    pub fn play(&self) {
+        let mut control = self.shared.lock();
+        match control.state {
+            PlaybackState::Playing => return,
+            PlaybackState::Finished => {
+                control.position = Duration::ZERO;
+                control.next = 0;
+            }
+            _ => {}
+        }
+        control.origin = self.clock.now() - control.position.div_f64(control.speed);
+        control.state = PlaybackState::Playing;
+        self.shared.cond.notify_all();
+    }
+
+    /// Pauses playback, cutting any sounding notes
+    pub fn pause(&self) {
+        let mut control = self.shared.lock();
+        if control.state == PlaybackState::Playing {
+            control.position = control.position_at(self.clock.now());
+            control.state = PlaybackState::Paused;
+            control.silence = true;
+            self.shared.cond.notify_all();
+        }
+    }
+
+    /// Stops playback and rewinds to the start, cutting any sounding notes
+    pub fn stop(&self) {
+        let mut control = self.shared.lock();
+        if matches!(
+            control.state,
+            PlaybackState::Playing | PlaybackState::Paused
+        ) {
+            control.silence = true;
+        }
+        control.state = PlaybackState::Stopped;
+        control.position = Duration::ZERO;
+        control.next = 0;
+        control.chase = false;
+        self.shared.cond.notify_all();
+    }
+
+    /// Jumps to `position` in the song. Keeps playing if the song was
+    /// playing, and pauses at `position` otherwise.
+    ///
+    /// Sounding notes are cut and the channel state at `position` is replayed.
+    pub fn seek(&self, position: Duration) {
+        let mut control = self.shared.lock();
+        let position = position.min(self.duration);
+        control.seek(&self.shared.events, position, self.clock.now());
+        if matches!(
+            control.state,
+            PlaybackState::Stopped | PlaybackState::Finished
+        ) {
+            control.state = PlaybackState::Paused;
+        }
+        self.shared.cond.notify_all();
+    }
+
+    /// Jumps to `tick` in the song, like [`seek`](Self::seek)
+    pub fn seek_tick(&self, tick: u64) {
+        self.seek(self.tempo_map.time_at(tick));
+    }
+
+    /// Sets a range of the song to repeat, or `None` to play through.
+    ///
+    /// When the position reaches the end of the range, it jumps back to the
+    /// start as if [`seek`](Self::seek) was called.
+    pub fn set_loop(&self, range: Option<Range<Duration>>) {
+        let mut control = self.shared.lock();
+        control.loop_range = range.filter(|range| range.start < range.end);
+        self.shared.cond.notify_all();
+    }
+
+    /// Sets the playback speed, clamped to [`MIN_SPEED`]..=[`MAX_SPEED`].
+    /// 1.0 is the original tempo.
+    pub fn set_speed(&self, speed: f64) {
+        let speed = if speed.is_nan() {
+            1.0
+        } else {
+            speed.clamp(MIN_SPEED, MAX_SPEED)
+        };
+
+        let mut control = self.shared.lock();
+        let now = self.clock.now();
+        let position = control.position_at(now);
+        control.speed = speed;
+        control.origin = now - position.div_f64(speed);
+        self.shared.cond.notify_all();
+    }
+
+    /// Returns the playback speed
+    pub fn speed(&self) -> f64 {
+        self.shared.lock().speed
+    }
+
+    /// Returns the current playback state
+    pub fn state(&self) -> PlaybackState {
+        self.shared.lock().state
+    }
+
+    /// Returns the current position in the song
+    pub fn position(&self) -> Duration {
+        self.shared
+            .lock()
+            .position_at(self.clock.now())
+            .min(self.duration)
+    }
+
+    /// Returns the time of the last event in the song
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// Blocks until every event due at the clock's current time has been
+    /// sent, along with any pending note cuts and chasing
+    pub fn wait_due(&self) {
+        let mut control = self.shared.lock();
+        while !control.quit && !control.is_caught_up(&self.shared.events, self.clock.now()) {
+            control = self
+                .shared
+                .idle
+                .wait(control)
+                .unwrap_or_else(|err| err.into_inner());
+        }
+    }
+
+    /// Stops the timing thread and returns the sink
+    ///
+    /// # Panics
+    ///
+    /// If the sink panicked on the timing thread.
+    pub fn into_sink(mut self) -> S {
+        self.shutdown().expect("the player thread panicked")
+    }
+
+    fn shutdown(&mut self) -> Option<S> {
+        {
+            let mut control = self.shared.lock();
+            control.quit = true;
+            self.shared.cond.notify_all();
+        }
+        self.thread.take().and_then(|thread| thread.join().ok())
+    }
+}
+
+impl<S: MidiSink + Send + 'static, C: Clock> Drop for MidiPlayer<S, C> {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Releases the sustain pedal and stops every sound on every channel, the
+/// same way [`KDMAPIStream::panic`](crate::KDMAPIStream::panic) does
+fn silence(sink: &mut impl MidiSink) {
+    for data in all_off_messages() {
+        let _ = sink.send_short(data);
+    }
+}
+
+/// Sends a note off for every sounding note, after releasing the sustain
+/// pedal so that they actually stop
+fn cut_notes(notes: &mut NoteTracker, sink: &mut impl MidiSink) {
+    for channel in 0..16 {
+        let _ = sink.send_short(0xB0 | channel | 64 << 8);
+    }
+    for data in notes.note_offs() {
+        let _ = sink.send_short(data);
+    }
+    notes.clear();
+}
+
+/// Replays the channel state built up by `events`, after resetting what a
+/// later part of the song may have set
+fn chase(events: &[PlayerEvent], sink: &mut impl MidiSink) {
+    let mut state = MidiState::new();
+    for event in events {
+        if let MidiEvent::Short(data) = event.event {
+            state.update(data);
+        }
+    }
+    // Reset All Controllers and a centered pitch bend
+    for channel in 0..16 {
+        let _ = sink.send_short(0xB0 | channel | 121 << 8);
+        let _ = sink.send_short(0xE0 | channel | 0x40 << 16);
+    }
+    for data in state.restore_messages() {
+        let _ = sink.send_short(data);
+    }
+}
+
+/// Marks the player as stopped when the timing thread exits, even by
+/// panicking, so that waiters don't wait for events that won't be sent
+struct StopOnExit<'a>(&'a Shared);
+
+impl Drop for StopOnExit<'_> {
+    fn drop(&mut self) {
+        let mut control = self.0.lock();
+        control.quit = true;
+        control.sending = false;
+        self.0.idle.notify_all();
+    }
+}
+
+fn run<S: MidiSink>(shared: &Shared, clock: &impl Clock, mut sink: S) -> S {
+    let _stop = StopOnExit(shared);
+    let events = &shared.events;
+    let mut sounding = NoteTracker::new();
+    let mut control = shared.lock();
+    loop {
+        if control.silence {
+            control.silence = false;
+            control.sending = true;
+            drop(control);
+            silence(&mut sink);
+            sounding.clear();
+            control = shared.lock();
+            control.sending = false;
+            continue;
+        }
+
+        if control.chase {
+            control.chase = false;
+            control.sending = true;
+            let next = control.next;
+            drop(control);
+            cut_notes(&mut sounding, &mut sink);
+            chase(&events[..next], &mut sink);
+            control = shared.lock();
+            control.sending = false;
+            continue;
+        }
+
+        // Checked after the pending cleanup, so it still happens when the
+        // player is dropped right after a stop or seek
+        if control.quit {
+            shared.idle.notify_all();
+            break;
+        }
+
+        if control.state != PlaybackState::Playing {
+            shared.idle.notify_all();
+            control = shared
+                .cond
+                .wait(control)
+                .unwrap_or_else(|err| err.into_inner());
+            continue;
+        }
+
+        let now = clock.now();
+        let position = control.position_at(now);
+        let loop_end = control.loop_range.as_ref().map(|range| range.end);
+
+        if let Some(range) = control.loop_range.clone() {
+            if position >= range.end {
+                // Keep the loop in step with real time rather than the
+                // moment this thread noticed the end
+                let end_instant = control.instant_at(range.end);
+                control.seek(events, range.start, end_instant);
+                continue;
+            }
+        }
+
+        let next_time = events.get(control.next).map(|event| event.time);
+        let target_time = match (next_time, loop_end) {
+            (Some(next), Some(end)) => next.min(end),
+            (Some(next), None) => next,
+            (None, Some(end)) => end,
+            (None, None) => {
+                control.position = position;
+                control.state = PlaybackState::Finished;
+                continue;
+            }
+        };
+
+        let target = control.instant_at(target_time);
+        if target > now {
+            let remaining = target - now;
+            let spin_threshold = clock.spin_threshold();
+            if remaining > spin_threshold {
+                shared.idle.notify_all();
+                control = clock.wait_until(&shared.cond, control, target - spin_threshold);
+            } else {
+                drop(control);
+                while clock.now() < target {
+                    std::hint::spin_loop();
+                }
+                control = shared.lock();
+            }
+            continue;
+        }
+
+        // Send everything that's due, without holding the lock
+        let start = control.next;
+        let end = start
+            + events[start..]
+                .iter()
+                .take_while(|event| {
+                    event.time <= position && loop_end.map_or(true, |end| event.time < end)
+                })
+                .count();
+        control.next = end;
+        control.sending = true;
+        drop(control);
+
+        for event in &events[start..end] {
+            if let MidiEvent::Short(data) = event.event {
+                sounding.update(data);
+            }
+            let _ = sink.send_event(&event.event);
+        }
+        control = shared.lock();
+        control.sending = false;
+    }
+    sink
+}
+
+/// Counters from a [`StreamingPlayer`] run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamingStats {
+    /// Events sent to the sink
+    pub events_sent: u64,
+    /// Note ons dropped because they were later than the allowed lag
+    pub notes_skipped: u64,
+    /// The furthest behind real time an event was sent
+    pub max_lag: Duration,
+}
+
+/// Plays merged events as they're decoded, e.g. from
+/// [`StreamingSmf::events`](crate::smf::StreamingSmf::events), for files too
+/// large to load with [`MidiPlayer`].
+///
+/// Every event that is due is sent in one batch before waiting for the next
+/// one, so the player catches up instead of drifting when it falls behind.
+/// Runs of short messages go to the sink through
+/// [`MidiSink::send_short_batch`].
+/// Optionally, note ons that are too late are dropped entirely, as black
+/// MIDI players usually do.
+///
+/// Times are read from a [`Clock`], the real time by default.
+pub struct StreamingPlayer<S: MidiSink, C: Clock = SystemClock> {
+    sink: S,
+    clock: C,
+    max_note_lag: Option<Duration>,
+    stop: Arc<AtomicBool>,
+    /// Due short messages not sent yet
+    batch: Vec<u32>,
+}
+
+impl<S: MidiSink> StreamingPlayer<S> {
+    /// Creates a player that sends to `sink`
+    pub fn new(sink: S) -> Self {
+        Self::with_clock(sink, SystemClock)
+    }
+}
+
+impl<S: MidiSink, C: Clock> StreamingPlayer<S, C> {
+    /// Like [`new`](StreamingPlayer::new), reading times from `clock`
+    pub fn with_clock(sink: S, clock: C) -> Self {
+        StreamingPlayer {
+            sink,
+            clock,
+            max_note_lag: None,
+            stop: Arc::new(AtomicBool::new(false)),
+            batch: Vec::new(),
+        }
+    }
+
+    /// Drops note ons that are more than `lag` behind real time. `None`
+    /// (the default) sends every note.
+    pub fn skip_late_notes(mut self, lag: Option<Duration>) -> Self {
+        self.max_note_lag = lag;
+        self
+    }
+
+    /// Returns a flag that stops [`play`](Self::play) when set, e.g. from
+    /// another thread
+    pub fn stop_handle(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    /// Plays `events` on the current thread, returning when they run out or
+    /// playback is stopped. Sounding notes are cut before returning.
+    ///
+    /// Events in a batch the sink returned an error for aren't counted as
+    /// sent.
+    ///
+    /// Errors if an event couldn't be decoded.
+    pub fn play<I>(&mut self, events: I) -> Result<StreamingStats, SmfError>
+    where
+        I: IntoIterator<Item = Result<TimedEvent, SmfError>>,
+    {
+        self.stop.store(false, Ordering::Relaxed);
+        let result = self.play_inner(events.into_iter());
+        self.batch.clear();
+        silence(&mut self.sink);
+        result
+    }
+
+    fn play_inner(
+        &mut self,
+        mut events: impl Iterator<Item = Result<TimedEvent, SmfError>>,
+    ) -> Result<StreamingStats, SmfError> {
+        let mut stats = StreamingStats::default();
+        let start = self.clock.now();
+        let mut next = events.next().transpose()?;
+
+        while let Some(event) = next.take() {
+            wait_until(&self.clock, start + event.time, &self.stop);
+            if self.stop.load(Ordering::Relaxed) {
+                break;
+            }
+            let position = self.clock.now().saturating_duration_since(start);
+            stats.max_lag = stats.max_lag.max(position.saturating_sub(event.time));
+
+            // Send the whole batch of due events before checking the time again
+            let mut due = Some(event);
+            while let Some(event) = due.take() {
+                self.send(&event, position, &mut stats);
+                match events.next().transpose()? {
+                    Some(event) if event.time <= position => due = Some(event),
+                    other => next = other,
+                }
+            }
+            self.flush(&mut stats);
+        }
+
+        Ok(stats)
+    }
+
+    /// Queues a short message for the next batch, or sends a SysEx right
+    /// away
+    fn send(&mut self, event: &TimedEvent, position: Duration, stats: &mut StreamingStats) {
+        match &event.kind {
+            EventKind::Midi(data) => {
+                let is_note_on = data & 0xF0 == 0x90 && data >> 16 & 0x7F != 0;
+                if is_note_on {
+                    if let Some(max_lag) = self.max_note_lag {
+                        if position.saturating_sub(event.time) > max_lag {
+                            stats.notes_skipped += 1;
+                            return;
+                        }
+                    }
+                }
+                self.batch.push(*data);
+            }
+            EventKind::SysEx(data) | EventKind::Escape(data) => {
+                if validate_sysex(data).is_ok() {
+                    // Keep the order of short messages and SysEx
+                    self.flush(stats);
+                    if self.sink.send_sysex(data).is_ok() {
+                        stats.events_sent += 1;
+                    }
+                }
+            }
+            EventKind::Meta(_) => {}
+        }
+    }
+
+    /// Sends the short messages collected so far in one batch
+    fn flush(&mut self, stats: &mut StreamingStats) {
+        if self.batch.is_empty() {
+            return;
+        }
+        if self.sink.send_short_batch(&self.batch).is_ok() {
+            stats.events_sent += self.batch.len() as u64;
+        }
+        self.batch.clear();
+    }
+
+    /// Returns the sink
+    pub fn into_sink(self) -> S {
+        self.sink
+    }
+}
+
+/// Waits on `clock` until shortly before `deadline`, then spins until it.
+/// Returns early once `stop` is set.
+fn wait_until(clock: &impl Clock, deadline: Instant, stop: &AtomicBool) {
+    let spin_start = deadline
+        .checked_sub(clock.spin_threshold())
+        .unwrap_or(deadline);
+    // Nothing notifies these, the clock's wait just needs something to
+    // wait on
+    let lock = Mutex::new(());
+    let cond = Condvar::new();
+    let mut guard = lock.lock().unwrap_or_else(|err| err.into_inner());
+    while clock.now() < spin_start && !stop.load(Ordering::Relaxed) {
+        guard = clock.wait_until(&cond, guard, spin_start);
+    }
+    drop(guard);
+    while clock.now() < deadline && !stop.load(Ordering::Relaxed) {
+        std::hint::spin_loop();
+    }
+}
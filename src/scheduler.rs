@@ -0,0 +1,328 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{Clock, MidiEvent, MidiSink, SystemClock};
+
+/// How far past its time an event can be sent before it counts as late
+pub const LATE_THRESHOLD: Duration = Duration::from_millis(1);
+
+/// Timing counters of a [`Scheduler`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SchedulerStats {
+    /// Events sent to the sink
+    pub events_sent: u64,
+    /// Events the sink returned an error for
+    pub send_errors: u64,
+    /// Events sent more than [`LATE_THRESHOLD`] after their time
+    pub late_events: u64,
+    /// The furthest past its time an event was sent
+    pub max_lateness: Duration,
+    /// Lateness of all sent events added up, for computing the average
+    pub total_lateness: Duration,
+}
+
+struct Scheduled {
+    at: Instant,
+    /// Keeps events with the same time in the order they were scheduled
+    seq: u64,
+    event: MidiEvent,
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Scheduled {}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.at, self.seq).cmp(&(other.at, other.seq))
+    }
+}
+
+struct Queue {
+    events: BinaryHeap<Reverse<Scheduled>>,
+    next_seq: u64,
+    /// Set while due events are being sent without the lock held
+    sending: bool,
+    stats: SchedulerStats,
+    /// Set when the dispatch thread stops, or is asked to
+    quit: bool,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    cond: Condvar,
+    idle: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// Sends events to a [`MidiSink`] at given points in time.
+///
+/// Scheduled events are kept in a priority queue and sent from a dedicated
+/// thread that owns the sink, sleeping until shortly before the next event
+/// and spinning for the rest. Events with the same time are sent in the
+/// order they were scheduled, and events whose time has already passed are
+/// sent right away. Send errors are counted in [`SchedulerStats`] and
+/// otherwise ignored.
+///
+/// Times are read from a [`Clock`], the real time by default. With a
+/// [`VirtualClock`](crate::VirtualClock), events are sent as the clock is
+/// advanced, and [`wait_due`](Self::wait_due) waits for them.
+///
+/// Pending events are discarded when the scheduler is dropped.
+pub struct Scheduler<S: MidiSink + Send + 'static, C: Clock = SystemClock> {
+    shared: Arc<Shared>,
+    clock: C,
+    thread: Option<JoinHandle<S>>,
+}
+
+impl<S: MidiSink + Send + 'static> Scheduler<S> {
+    /// Starts the dispatch thread, which takes ownership of `sink`
+    pub fn new(sink: S) -> Self {
+        Self::with_clock(sink, SystemClock)
+    }
+}
+
+impl<S: MidiSink + Send + 'static, C: Clock> Scheduler<S, C> {
+    /// Starts the dispatch thread, which takes ownership of `sink`, reading
+    /// times from `clock`
+    pub fn with_clock(sink: S, clock: C) -> Self {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue {
+                events: BinaryHeap::new(),
+                next_seq: 0,
+                sending: false,
+                stats: SchedulerStats::default(),
+                quit: false,
+            }),
+            cond: Condvar::new(),
+            idle: Condvar::new(),
+        });
+
+        let thread = {
+            let shared = shared.clone();
+            let clock = clock.clone();
+            thread::spawn(move || run(&shared, &clock, sink))
+        };
+
+        Scheduler {
+            shared,
+            clock,
+            thread: Some(thread),
+        }
+    }
+
+    /// Queues `event` to be sent at `at`
+    pub fn schedule(&self, at: Instant, event: impl Into<MidiEvent>) {
+        self.schedule_all(Some((at, event.into())));
+    }
+
+    /// Queues `event` to be sent `delay` from now
+    pub fn schedule_in(&self, delay: Duration, event: impl Into<MidiEvent>) {
+        self.schedule(self.clock.now() + delay, event);
+    }
+
+    /// Queues every `(time, event)` pair at once
+    pub fn schedule_all<I, E>(&self, events: I)
+    where
+        I: IntoIterator<Item = (Instant, E)>,
+        E: Into<MidiEvent>,
+    {
+        let mut queue = self.shared.lock();
+        for (at, event) in events {
+            let seq = queue.next_seq;
+            queue.next_seq += 1;
+            queue.events.push(Reverse(Scheduled {
+                at,
+                seq,
+                event: event.into(),
+            }));
+        }
+        self.shared.cond.notify_all();
+    }
+
+    /// Returns the number of events waiting to be sent
+    pub fn pending(&self) -> usize {
+        self.shared.lock().events.len()
+    }
+
+    /// Discards every event waiting to be sent
+    pub fn clear(&self) {
+        self.shared.lock().events.clear();
+        self.shared.cond.notify_all();
+        self.shared.idle.notify_all();
+    }
+
+    /// Returns the timing counters so far
+    pub fn stats(&self) -> SchedulerStats {
+        self.shared.lock().stats
+    }
+
+    /// Blocks until every scheduled event has been sent, or the dispatch
+    /// thread has stopped
+    pub fn wait_idle(&self) {
+        let mut queue = self.shared.lock();
+        while !queue.quit && (!queue.events.is_empty() || queue.sending) {
+            queue = self
+                .shared
+                .idle
+                .wait(queue)
+                .unwrap_or_else(|err| err.into_inner());
+        }
+    }
+
+    /// Blocks until every event that is due at the clock's current time has
+    /// been sent, or the dispatch thread has stopped
+    pub fn wait_due(&self) {
+        let mut queue = self.shared.lock();
+        while !queue.quit {
+            let now = self.clock.now();
+            let due = queue
+                .events
+                .peek()
+                .is_some_and(|Reverse(event)| event.at <= now);
+            if !due && !queue.sending {
+                break;
+            }
+            queue = self
+                .shared
+                .idle
+                .wait(queue)
+                .unwrap_or_else(|err| err.into_inner());
+        }
+    }
+
+    /// Stops the dispatch thread, discarding pending events, and returns the
+    /// sink
+    ///
+    /// # Panics
+    ///
+    /// If the sink panicked on the dispatch thread.
+    pub fn into_sink(mut self) -> S {
+        self.shutdown().expect("the dispatch thread panicked")
+    }
+
+    fn shutdown(&mut self) -> Option<S> {
+        {
+            let mut queue = self.shared.lock();
+            queue.quit = true;
+            queue.events.clear();
+            self.shared.cond.notify_all();
+            self.shared.idle.notify_all();
+        }
+        self.thread.take().and_then(|thread| thread.join().ok())
+    }
+}
+
+impl<S: MidiSink + Send + 'static, C: Clock> Drop for Scheduler<S, C> {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Marks the queue as stopped when the dispatch thread exits, even by
+/// panicking, so that waiters don't wait for events that won't be sent
+struct StopOnExit<'a>(&'a Shared);
+
+impl Drop for StopOnExit<'_> {
+    fn drop(&mut self) {
+        let mut queue = self.0.lock();
+        queue.quit = true;
+        queue.sending = false;
+        queue.events.clear();
+        self.0.idle.notify_all();
+    }
+}
+
+fn run<S: MidiSink>(shared: &Shared, clock: &impl Clock, mut sink: S) -> S {
+    let _stop = StopOnExit(shared);
+    let mut due = Vec::new();
+    let mut queue = shared.lock();
+    loop {
+        if queue.quit {
+            break;
+        }
+
+        let next = match queue.events.peek() {
+            Some(Reverse(event)) => event.at,
+            None => {
+                shared.idle.notify_all();
+                queue = shared
+                    .cond
+                    .wait(queue)
+                    .unwrap_or_else(|err| err.into_inner());
+                continue;
+            }
+        };
+
+        let now = clock.now();
+        if next > now {
+            let remaining = next - now;
+            let spin_threshold = clock.spin_threshold();
+            if remaining > spin_threshold {
+                shared.idle.notify_all();
+                queue = clock.wait_until(&shared.cond, queue, next - spin_threshold);
+            } else {
+                drop(queue);
+                while clock.now() < next {
+                    std::hint::spin_loop();
+                }
+                queue = shared.lock();
+            }
+            continue;
+        }
+
+        // Send everything that's due, without holding the lock
+        while let Some(Reverse(event)) = queue.events.peek() {
+            if event.at > now {
+                break;
+            }
+            due.extend(queue.events.pop().map(|Reverse(event)| event));
+        }
+        queue.sending = true;
+        drop(queue);
+
+        let mut stats = SchedulerStats::default();
+        for event in due.drain(..) {
+            let lateness = clock.now().saturating_duration_since(event.at);
+            match sink.send_event(&event.event) {
+                Ok(()) => stats.events_sent += 1,
+                Err(_) => stats.send_errors += 1,
+            }
+            if lateness > LATE_THRESHOLD {
+                stats.late_events += 1;
+            }
+            stats.max_lateness = stats.max_lateness.max(lateness);
+            stats.total_lateness += lateness;
+        }
+
+        queue = shared.lock();
+        queue.sending = false;
+        let total = &mut queue.stats;
+        total.events_sent += stats.events_sent;
+        total.send_errors += stats.send_errors;
+        total.late_events += stats.late_events;
+        total.max_lateness = total.max_lateness.max(stats.max_lateness);
+        total.total_lateness += stats.total_lateness;
+    }
+    sink
+}
@@ -0,0 +1,302 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, OnceLock,
+    },
+    thread::{self, JoinHandle, Thread},
+    time::Duration,
+};
+
+use crate::{ring::RingBuffer, MidiEvent, MidiSink};
+
+/// How long the sender thread sleeps when the queue is empty, in case a
+/// wakeup is missed
+const IDLE_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// How long a blocked [`StreamSender::send`] sleeps between retries, once
+/// spinning and yielding didn't free a slot
+const BLOCKED_SLEEP: Duration = Duration::from_micros(50);
+
+/// What [`StreamSender::send`] does when the queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wait until the sender thread has made room
+    Block,
+    /// Discard the oldest queued event to make room
+    DropOldest,
+    /// Discard the event being sent, returning [`QueueError::Full`]
+    DropNewest,
+}
+
+/// Options for [`SenderThread::spawn`]
+#[derive(Debug, Clone)]
+pub struct SenderOptions {
+    /// Events the queue holds, rounded up to a power of two
+    pub capacity: usize,
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for SenderOptions {
+    fn default() -> Self {
+        SenderOptions {
+            capacity: 4096,
+            overflow: OverflowPolicy::Block,
+        }
+    }
+}
+
+/// Errors from [`StreamSender::send`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueueError {
+    /// The queue was full and the overflow policy is
+    /// [`OverflowPolicy::DropNewest`]
+    Full,
+    /// The sender thread has been stopped
+    Closed,
+}
+
+impl fmt::Display for QueueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueueError::Full => write!(f, "the event queue is full"),
+            QueueError::Closed => write!(f, "the sender thread has been stopped"),
+        }
+    }
+}
+
+impl std::error::Error for QueueError {}
+
+/// Counters of a [`SenderThread`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SenderStats {
+    /// Events sent to the sink
+    pub events_sent: u64,
+    /// Events discarded because the queue was full
+    pub events_dropped: u64,
+    /// Events the sink returned an error for
+    pub send_errors: u64,
+}
+
+struct Shared {
+    queue: RingBuffer<MidiEvent>,
+    overflow: OverflowPolicy,
+    closed: AtomicBool,
+    /// Producers between their check of `closed` and the end of their push,
+    /// which the sender thread waits for before exiting
+    pushing: AtomicUsize,
+    /// Set while the sender thread is about to park
+    sleeping: AtomicBool,
+    consumer: OnceLock<Thread>,
+    events_sent: AtomicU64,
+    events_dropped: AtomicU64,
+    send_errors: AtomicU64,
+}
+
+impl Shared {
+    fn wake_consumer(&self) {
+        if self.sleeping.swap(false, Ordering::SeqCst) {
+            if let Some(consumer) = self.consumer.get() {
+                consumer.unpark();
+            }
+        }
+    }
+}
+
+/// A handle for queueing events to a [`SenderThread`] from any thread.
+///
+/// Cheap to clone. Queueing never takes a lock: events go into a bounded
+/// lock-free ring buffer, and the full-queue behavior is set by the
+/// [`OverflowPolicy`]. Events from one handle are sent in the order they
+/// were queued.
+#[derive(Clone)]
+pub struct StreamSender {
+    shared: Arc<Shared>,
+}
+
+impl StreamSender {
+    /// Queues `event` to be sent by the sender thread
+    pub fn send(&self, event: impl Into<MidiEvent>) -> Result<(), QueueError> {
+        let shared = &self.shared;
+        let mut event = event.into();
+        let mut attempts = 0u32;
+        loop {
+            shared.pushing.fetch_add(1, Ordering::SeqCst);
+            if shared.closed.load(Ordering::SeqCst) {
+                shared.pushing.fetch_sub(1, Ordering::SeqCst);
+                return Err(QueueError::Closed);
+            }
+            let result = shared.queue.push(event);
+            shared.pushing.fetch_sub(1, Ordering::SeqCst);
+            event = match result {
+                Ok(()) => {
+                    shared.wake_consumer();
+                    return Ok(());
+                }
+                Err(event) => event,
+            };
+
+            match shared.overflow {
+                OverflowPolicy::DropNewest => {
+                    shared.events_dropped.fetch_add(1, Ordering::Relaxed);
+                    return Err(QueueError::Full);
+                }
+                OverflowPolicy::DropOldest => {
+                    if shared.queue.pop().is_some() {
+                        shared.events_dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                OverflowPolicy::Block => {
+                    shared.wake_consumer();
+                    attempts += 1;
+                    if attempts < 64 {
+                        std::hint::spin_loop();
+                    } else if attempts < 128 {
+                        thread::yield_now();
+                    } else {
+                        thread::sleep(BLOCKED_SLEEP);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns whether the sender thread has been stopped
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed.load(Ordering::Acquire)
+    }
+}
+
+/// A thread that owns a [`MidiSink`] (usually a
+/// [`KDMAPIStream`](crate::KDMAPIStream)) and sends everything queued
+/// through its [`StreamSender`] handles.
+///
+/// Lets any number of threads send MIDI without sharing the stream itself.
+/// Send errors are counted in [`SenderStats`] and otherwise ignored.
+///
+/// Events still queued when it's stopped are sent before the thread exits.
+pub struct SenderThread<S: MidiSink + Send + 'static> {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<S>>,
+}
+
+impl<S: MidiSink + Send + 'static> SenderThread<S> {
+    /// Starts the sender thread, which takes ownership of `sink`
+    pub fn spawn(sink: S, options: SenderOptions) -> Self {
+        let shared = Arc::new(Shared {
+            queue: RingBuffer::new(options.capacity),
+            overflow: options.overflow,
+            closed: AtomicBool::new(false),
+            pushing: AtomicUsize::new(0),
+            sleeping: AtomicBool::new(false),
+            consumer: OnceLock::new(),
+            events_sent: AtomicU64::new(0),
+            events_dropped: AtomicU64::new(0),
+            send_errors: AtomicU64::new(0),
+        });
+
+        let thread = {
+            let shared = shared.clone();
+            thread::spawn(move || run(&shared, sink))
+        };
+
+        SenderThread {
+            shared,
+            thread: Some(thread),
+        }
+    }
+
+    /// Returns a new handle for queueing events
+    pub fn sender(&self) -> StreamSender {
+        StreamSender {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Returns the number of events the queue holds
+    pub fn capacity(&self) -> usize {
+        self.shared.queue.capacity()
+    }
+
+    /// Returns the counters so far
+    pub fn stats(&self) -> SenderStats {
+        SenderStats {
+            events_sent: self.shared.events_sent.load(Ordering::Relaxed),
+            events_dropped: self.shared.events_dropped.load(Ordering::Relaxed),
+            send_errors: self.shared.send_errors.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Stops the thread after it has sent what's queued, and returns the
+    /// sink. Sending through the handles fails from then on.
+    ///
+    /// # Panics
+    ///
+    /// If the sink panicked on the sender thread.
+    pub fn into_sink(mut self) -> S {
+        self.shutdown().expect("the sender thread panicked")
+    }
+
+    fn shutdown(&mut self) -> Option<S> {
+        self.shared.closed.store(true, Ordering::SeqCst);
+        if let Some(consumer) = self.shared.consumer.get() {
+            consumer.unpark();
+        }
+        self.thread.take().and_then(|thread| thread.join().ok())
+    }
+}
+
+impl<S: MidiSink + Send + 'static> Drop for SenderThread<S> {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Closes the queue when the sender thread exits, even by panicking, so
+/// that blocked senders give up
+struct CloseOnExit<'a>(&'a Shared);
+
+impl Drop for CloseOnExit<'_> {
+    fn drop(&mut self) {
+        self.0.closed.store(true, Ordering::SeqCst);
+    }
+}
+
+fn run<S: MidiSink>(shared: &Shared, mut sink: S) -> S {
+    let _close = CloseOnExit(shared);
+    let _ = shared.consumer.set(thread::current());
+    loop {
+        let event = match shared.queue.pop() {
+            Some(event) => event,
+            None => {
+                if shared.closed.load(Ordering::SeqCst) {
+                    // A sender that saw the queue open may still be pushing.
+                    // Once none are, later senders see it closed, so what's
+                    // queued now is all there will be.
+                    if shared.pushing.load(Ordering::SeqCst) == 0 && shared.queue.is_empty() {
+                        break;
+                    }
+                    thread::yield_now();
+                    continue;
+                }
+                shared.sleeping.store(true, Ordering::SeqCst);
+                if shared.queue.is_empty() && !shared.closed.load(Ordering::Acquire) {
+                    thread::park_timeout(IDLE_TIMEOUT);
+                }
+                shared.sleeping.store(false, Ordering::SeqCst);
+                continue;
+            }
+        };
+
+        send_event(shared, &mut sink, &event);
+    }
+    sink
+}
+
+fn send_event(shared: &Shared, sink: &mut impl MidiSink, event: &MidiEvent) {
+    let counter = match sink.send_event(event) {
+        Ok(()) => &shared.events_sent,
+        Err(_) => &shared.send_errors,
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+}
@@ -0,0 +1,139 @@
+//! Typed keys for OmniMIDI's driver settings, for
+//! [`KDMAPIBinds::driver_setting`](crate::KDMAPIBinds::driver_setting) and
+//! [`KDMAPIBinds::set_driver_setting`](crate::KDMAPIBinds::set_driver_setting).
+//!
+//! ```no_run
+//! use kdmapi::{settings, KDMAPI};
+//!
+//! let binds = KDMAPI.as_ref().unwrap();
+//! let voices = binds.driver_setting(settings::MAX_VOICES).unwrap();
+//! binds.set_driver_setting(settings::MAX_VOICES, voices * 2).unwrap();
+//! binds.set_driver_setting(settings::IGNORE_SYSEX, true).unwrap();
+//! ```
+
+use std::marker::PhantomData;
+
+mod private {
+    pub trait Sealed {}
+}
+
+/// A type a setting can have. Every setting is stored as a `DWORD`.
+pub trait SettingValue: private::Sealed + Copy {
+    #[doc(hidden)]
+    fn to_raw(self) -> u32;
+    #[doc(hidden)]
+    fn from_raw(raw: u32) -> Self;
+}
+
+impl private::Sealed for bool {}
+
+impl SettingValue for bool {
+    fn to_raw(self) -> u32 {
+        self as u32
+    }
+
+    fn from_raw(raw: u32) -> Self {
+        raw != 0
+    }
+}
+
+impl private::Sealed for u32 {}
+
+impl SettingValue for u32 {
+    fn to_raw(self) -> u32 {
+        self
+    }
+
+    fn from_raw(raw: u32) -> Self {
+        raw
+    }
+}
+
+/// A driver setting with values of type `T`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Setting<T: SettingValue> {
+    code: u32,
+    _value: PhantomData<T>,
+}
+
+impl<T: SettingValue> Setting<T> {
+    /// Creates a key for a setting code that has no constant here, e.g. one
+    /// added in a newer OmniMIDI version
+    pub const fn new(code: u32) -> Self {
+        Setting {
+            code,
+            _value: PhantomData,
+        }
+    }
+
+    /// Returns the `OM_*` code passed to `DriverSettings`
+    pub const fn code(&self) -> u32 {
+        self.code
+    }
+}
+
+/// `OM_CAPFRAMERATE`
+pub const CAP_FRAMERATE: Setting<bool> = Setting::new(0x10000);
+/// `OM_DEBUGMMODE`
+pub const DEBUG_MODE: Setting<bool> = Setting::new(0x10001);
+/// `OM_DISABLEFADEOUT`
+pub const DISABLE_FADEOUT: Setting<bool> = Setting::new(0x10002);
+/// `OM_DONTMISSNOTES`
+pub const DONT_MISS_NOTES: Setting<bool> = Setting::new(0x10003);
+/// `OM_ENABLESFX`
+pub const ENABLE_SFX: Setting<bool> = Setting::new(0x10004);
+/// `OM_FULLVELOCITY`
+pub const FULL_VELOCITY: Setting<bool> = Setting::new(0x10005);
+/// `OM_IGNOREVELOCITYRANGE`, ignore notes with velocities between
+/// [`MIN_IGNORE_VELOCITY`] and [`MAX_IGNORE_VELOCITY`]
+pub const IGNORE_VELOCITY_RANGE: Setting<bool> = Setting::new(0x10006);
+/// `OM_IGNOREALLEVENTS`
+pub const IGNORE_ALL_EVENTS: Setting<bool> = Setting::new(0x10007);
+/// `OM_IGNORESYSEX`
+pub const IGNORE_SYSEX: Setting<bool> = Setting::new(0x10008);
+/// `OM_IGNORESYSRESET`
+pub const IGNORE_SYSRESET: Setting<bool> = Setting::new(0x10009);
+/// `OM_LIMITRANGETO88`
+pub const LIMIT_RANGE_TO_88: Setting<bool> = Setting::new(0x10010);
+/// `OM_MT32MODE`
+pub const MT32_MODE: Setting<bool> = Setting::new(0x10011);
+/// `OM_MONORENDERING`
+pub const MONO_RENDERING: Setting<bool> = Setting::new(0x10012);
+/// `OM_NOTEOFF1`
+pub const NOTE_OFF_1: Setting<bool> = Setting::new(0x10013);
+/// `OM_EVENTPROCWITHAUDIO`
+pub const EVENT_PROC_WITH_AUDIO: Setting<bool> = Setting::new(0x10014);
+/// `OM_SINCINTER`
+pub const SINC_INTERPOLATION: Setting<bool> = Setting::new(0x10015);
+/// `OM_SLEEPSTATES`
+pub const SLEEP_STATES: Setting<bool> = Setting::new(0x10016);
+/// `OM_AUDIOBITDEPTH`
+pub const AUDIO_BIT_DEPTH: Setting<u32> = Setting::new(0x10017);
+/// `OM_AUDIOFREQ`, in Hz
+pub const AUDIO_FREQUENCY: Setting<u32> = Setting::new(0x10018);
+/// `OM_CURRENTENGINE`, the audio output
+pub const CURRENT_ENGINE: Setting<u32> = Setting::new(0x10019);
+/// `OM_BUFFERLENGTH`
+pub const BUFFER_LENGTH: Setting<u32> = Setting::new(0x10020);
+/// `OM_MAXRENDERINGTIME`, in percent
+pub const MAX_RENDERING_TIME: Setting<u32> = Setting::new(0x10021);
+/// `OM_MINIGNOREVELRANGE`
+pub const MIN_IGNORE_VELOCITY: Setting<u32> = Setting::new(0x10022);
+/// `OM_MAXIGNOREVELRANGE`
+pub const MAX_IGNORE_VELOCITY: Setting<u32> = Setting::new(0x10023);
+/// `OM_OUTPUTVOLUME`
+pub const OUTPUT_VOLUME: Setting<u32> = Setting::new(0x10024);
+/// `OM_TRANSPOSE`
+pub const TRANSPOSE: Setting<u32> = Setting::new(0x10025);
+/// `OM_MAXVOICES`
+pub const MAX_VOICES: Setting<u32> = Setting::new(0x10026);
+/// `OM_SINCINTERCONV`
+pub const SINC_INTERPOLATION_CONVERSION: Setting<u32> = Setting::new(0x10027);
+/// `OM_OVERRIDENOTELENGTH`
+pub const OVERRIDE_NOTE_LENGTH: Setting<bool> = Setting::new(0x10028);
+/// `OM_NOTELENGTH`, in milliseconds
+pub const NOTE_LENGTH: Setting<u32> = Setting::new(0x10029);
+/// `OM_ENABLEDELAYNOTEOFF`
+pub const ENABLE_DELAY_NOTE_OFF: Setting<bool> = Setting::new(0x10030);
+/// `OM_DELAYNOTEOFFVAL`, in milliseconds
+pub const DELAY_NOTE_OFF_VALUE: Setting<u32> = Setting::new(0x10031);
@@ -0,0 +1,153 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::{KDMAPIStream, KdmapiError, MidiSink, ShortMessage};
+
+/// A [`KDMAPIStream`] that can be shared between threads, e.g. in an
+/// [`Arc`].
+///
+/// Every call locks an internal mutex, so calls from different threads
+/// never overlap inside OmniMIDI. Use [`lock`](Self::lock) to make several
+/// calls in a row without other threads getting in between.
+///
+/// Not to be confused with
+/// [`KDMAPIBinds::open_shared_stream`](crate::KDMAPIBinds::open_shared_stream),
+/// which lets several independent streams use the same KDMAPI stream.
+///
+/// A plain [`KDMAPIStream`] can't be shared this way:
+///
+/// ```compile_fail
+/// fn share<T: Sync>(_: &T) {}
+///
+/// let stream = kdmapi::KDMAPI.as_ref().unwrap().open_stream().unwrap();
+/// share(&stream);
+/// ```
+pub struct SharedStream {
+    stream: Mutex<KDMAPIStream>,
+}
+
+impl SharedStream {
+    /// Wraps `stream` for sharing
+    pub fn new(stream: KDMAPIStream) -> Self {
+        SharedStream {
+            stream: Mutex::new(stream),
+        }
+    }
+
+    /// Locks the stream for exclusive use until the guard is dropped
+    pub fn lock(&self) -> MutexGuard<'_, KDMAPIStream> {
+        self.stream.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Returns the wrapped stream
+    pub fn into_inner(self) -> KDMAPIStream {
+        self.stream
+            .into_inner()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Calls [`KDMAPIStream::send_direct_data`]
+    pub fn send_direct_data(&self, data: u32) -> Result<(), KdmapiError> {
+        self.lock().send_direct_data(data)
+    }
+
+    /// Calls [`KDMAPIStream::send_direct_data_no_buf`]
+    pub fn send_direct_data_no_buf(&self, data: u32) -> Result<(), KdmapiError> {
+        self.lock().send_direct_data_no_buf(data)
+    }
+
+    /// Calls [`KDMAPIStream::send`]
+    pub fn send(&self, message: ShortMessage) -> Result<(), KdmapiError> {
+        self.lock().send(message)
+    }
+
+    /// Calls [`KDMAPIStream::send_no_buf`]
+    pub fn send_no_buf(&self, message: ShortMessage) -> Result<(), KdmapiError> {
+        self.lock().send_no_buf(message)
+    }
+
+    /// Calls [`KDMAPIStream::send_batch`], keeping the lock for the whole
+    /// batch
+    pub fn send_batch(&self, data: &[u32]) -> Result<(), KdmapiError> {
+        self.lock().send_batch(data)
+    }
+
+    /// Calls [`KDMAPIStream::send_batch_no_buf`], keeping the lock for the
+    /// whole batch
+    pub fn send_batch_no_buf(&self, data: &[u32]) -> Result<(), KdmapiError> {
+        self.lock().send_batch_no_buf(data)
+    }
+
+    /// Calls [`KDMAPIStream::send_sysex`]
+    pub fn send_sysex(&self, data: &[u8]) -> Result<(), KdmapiError> {
+        self.lock().send_sysex(data)
+    }
+
+    /// Calls [`KDMAPIStream::send_sysex_no_buf`]
+    pub fn send_sysex_no_buf(&self, data: &[u8]) -> Result<(), KdmapiError> {
+        self.lock().send_sysex_no_buf(data)
+    }
+
+    /// Calls [`KDMAPIStream::reset`]
+    pub fn reset(&self) {
+        self.lock().reset()
+    }
+
+    /// Calls [`KDMAPIStream::panic`]
+    pub fn panic(&self) -> Result<(), KdmapiError> {
+        self.lock().panic()
+    }
+}
+
+impl From<KDMAPIStream> for SharedStream {
+    fn from(stream: KDMAPIStream) -> Self {
+        SharedStream::new(stream)
+    }
+}
+
+impl MidiSink for SharedStream {
+    fn send_short(&mut self, data: u32) -> Result<(), KdmapiError> {
+        self.send_direct_data(data)
+    }
+
+    fn send_short_no_buf(&mut self, data: u32) -> Result<(), KdmapiError> {
+        self.send_direct_data_no_buf(data)
+    }
+
+    fn send_sysex(&mut self, data: &[u8]) -> Result<(), KdmapiError> {
+        SharedStream::send_sysex(self, data)
+    }
+
+    fn send_short_batch(&mut self, data: &[u32]) -> Result<(), KdmapiError> {
+        self.send_batch(data)
+    }
+
+    fn reset(&mut self) -> Result<(), KdmapiError> {
+        SharedStream::reset(self);
+        Ok(())
+    }
+}
+
+/// Lets a player or scheduler send to a stream that other threads keep
+/// using
+impl MidiSink for Arc<SharedStream> {
+    fn send_short(&mut self, data: u32) -> Result<(), KdmapiError> {
+        self.send_direct_data(data)
+    }
+
+    fn send_short_no_buf(&mut self, data: u32) -> Result<(), KdmapiError> {
+        self.send_direct_data_no_buf(data)
+    }
+
+    fn send_sysex(&mut self, data: &[u8]) -> Result<(), KdmapiError> {
+        SharedStream::send_sysex(self, data)
+    }
+
+    fn send_short_batch(&mut self, data: &[u32]) -> Result<(), KdmapiError> {
+        self.send_batch(data)
+    }
+
+    fn reset(&mut self) -> Result<(), KdmapiError> {
+        SharedStream::reset(self);
+        Ok(())
+    }
+}
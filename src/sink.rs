@@ -0,0 +1,230 @@
+use std::time::Instant;
+
+use crate::{validate_sysex, Clock, KDMAPIStream, KdmapiError, ShortMessage, SystemClock};
+
+/// Something that MIDI data can be sent to.
+///
+/// [`KDMAPIStream`] is the main implementation. [`RecordingSink`] keeps
+/// everything in memory, for testing code without OmniMIDI installed.
+pub trait MidiSink {
+    /// Sends a packed short message, like `SendDirectData`
+    fn send_short(&mut self, data: u32) -> Result<(), KdmapiError>;
+
+    /// Sends a packed short message, like `SendDirectDataNoBuf`
+    fn send_short_no_buf(&mut self, data: u32) -> Result<(), KdmapiError>;
+
+    /// Sends a complete SysEx message, starting with `F0` and ending with `F7`
+    fn send_sysex(&mut self, data: &[u8]) -> Result<(), KdmapiError>;
+
+    /// Sends packed short messages in order, stopping at the first error.
+    ///
+    /// Calls [`send_short`](Self::send_short) for each by default; sinks
+    /// override it where sending in bulk is cheaper.
+    fn send_short_batch(&mut self, data: &[u32]) -> Result<(), KdmapiError> {
+        data.iter().try_for_each(|&data| self.send_short(data))
+    }
+
+    /// Resets the synth, like `ResetKDMAPIStream`
+    fn reset(&mut self) -> Result<(), KdmapiError>;
+
+    /// Sends a short message or SysEx, depending on the event
+    fn send_event(&mut self, event: &MidiEvent) -> Result<(), KdmapiError> {
+        match event {
+            MidiEvent::Short(data) => self.send_short(*data),
+            MidiEvent::SysEx(data) => self.send_sysex(data),
+        }
+    }
+}
+
+/// Data that can be sent to a [`MidiSink`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MidiEvent {
+    /// A packed short message
+    Short(u32),
+    /// A complete SysEx message, starting with `F0` and ending with `F7`
+    SysEx(Vec<u8>),
+}
+
+impl From<u32> for MidiEvent {
+    fn from(data: u32) -> Self {
+        MidiEvent::Short(data)
+    }
+}
+
+impl From<ShortMessage> for MidiEvent {
+    fn from(message: ShortMessage) -> Self {
+        MidiEvent::Short(message.pack())
+    }
+}
+
+impl MidiSink for KDMAPIStream {
+    fn send_short(&mut self, data: u32) -> Result<(), KdmapiError> {
+        self.send_direct_data(data)
+    }
+
+    fn send_short_no_buf(&mut self, data: u32) -> Result<(), KdmapiError> {
+        self.send_direct_data_no_buf(data)
+    }
+
+    fn send_sysex(&mut self, data: &[u8]) -> Result<(), KdmapiError> {
+        KDMAPIStream::send_sysex(self, data)
+    }
+
+    fn send_short_batch(&mut self, data: &[u32]) -> Result<(), KdmapiError> {
+        self.send_batch(data)
+    }
+
+    fn reset(&mut self) -> Result<(), KdmapiError> {
+        KDMAPIStream::reset(self);
+        Ok(())
+    }
+}
+
+impl<T: MidiSink + ?Sized> MidiSink for &mut T {
+    fn send_short(&mut self, data: u32) -> Result<(), KdmapiError> {
+        (**self).send_short(data)
+    }
+
+    fn send_short_no_buf(&mut self, data: u32) -> Result<(), KdmapiError> {
+        (**self).send_short_no_buf(data)
+    }
+
+    fn send_sysex(&mut self, data: &[u8]) -> Result<(), KdmapiError> {
+        (**self).send_sysex(data)
+    }
+
+    fn send_short_batch(&mut self, data: &[u32]) -> Result<(), KdmapiError> {
+        (**self).send_short_batch(data)
+    }
+
+    fn reset(&mut self) -> Result<(), KdmapiError> {
+        (**self).reset()
+    }
+}
+
+impl<T: MidiSink + ?Sized> MidiSink for Box<T> {
+    fn send_short(&mut self, data: u32) -> Result<(), KdmapiError> {
+        (**self).send_short(data)
+    }
+
+    fn send_short_no_buf(&mut self, data: u32) -> Result<(), KdmapiError> {
+        (**self).send_short_no_buf(data)
+    }
+
+    fn send_sysex(&mut self, data: &[u8]) -> Result<(), KdmapiError> {
+        (**self).send_sysex(data)
+    }
+
+    fn send_short_batch(&mut self, data: &[u32]) -> Result<(), KdmapiError> {
+        (**self).send_short_batch(data)
+    }
+
+    fn reset(&mut self) -> Result<(), KdmapiError> {
+        (**self).reset()
+    }
+}
+
+/// A call received by a [`RecordingSink`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SinkEvent {
+    /// [`MidiSink::send_short`]
+    Short(u32),
+    /// [`MidiSink::send_short_no_buf`]
+    ShortNoBuf(u32),
+    /// [`MidiSink::send_sysex`]
+    SysEx(Vec<u8>),
+    /// [`MidiSink::reset`]
+    Reset,
+}
+
+/// A [`SinkEvent`] along with when it was received
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedEvent {
+    /// When the call was made
+    pub time: Instant,
+    /// The call itself
+    pub event: SinkEvent,
+}
+
+/// A [`MidiSink`] that records every call in memory.
+///
+/// Calls are timestamped with a [`Clock`], so with a
+/// [`VirtualClock`](crate::VirtualClock) the recorded times are exact.
+#[derive(Debug, Clone, Default)]
+pub struct RecordingSink<C: Clock = SystemClock> {
+    events: Vec<RecordedEvent>,
+    clock: C,
+}
+
+impl RecordingSink {
+    /// Creates an empty recording sink
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<C: Clock> RecordingSink<C> {
+    /// Creates an empty recording sink that timestamps calls with `clock`
+    pub fn with_clock(clock: C) -> Self {
+        RecordingSink {
+            events: Vec::new(),
+            clock,
+        }
+    }
+
+    /// Returns everything received so far, oldest first
+    pub fn events(&self) -> &[RecordedEvent] {
+        &self.events
+    }
+
+    /// Returns everything received so far and clears the recording
+    pub fn take_events(&mut self) -> Vec<RecordedEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Returns the packed short messages received so far, buffered or not
+    pub fn short_messages(&self) -> Vec<u32> {
+        self.events
+            .iter()
+            .filter_map(|recorded| match recorded.event {
+                SinkEvent::Short(data) | SinkEvent::ShortNoBuf(data) => Some(data),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Clears the recording
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+
+    fn record(&mut self, event: SinkEvent) {
+        self.events.push(RecordedEvent {
+            time: self.clock.now(),
+            event,
+        });
+    }
+}
+
+impl<C: Clock> MidiSink for RecordingSink<C> {
+    fn send_short(&mut self, data: u32) -> Result<(), KdmapiError> {
+        self.record(SinkEvent::Short(data));
+        Ok(())
+    }
+
+    fn send_short_no_buf(&mut self, data: u32) -> Result<(), KdmapiError> {
+        self.record(SinkEvent::ShortNoBuf(data));
+        Ok(())
+    }
+
+    fn send_sysex(&mut self, data: &[u8]) -> Result<(), KdmapiError> {
+        validate_sysex(data)?;
+        self.record(SinkEvent::SysEx(data.to_vec()));
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Result<(), KdmapiError> {
+        self.record(SinkEvent::Reset);
+        Ok(())
+    }
+}
@@ -0,0 +1,517 @@
+use std::{
+    fmt, fs,
+    io::{self, Read},
+    path::Path,
+    time::Duration,
+};
+
+mod stream;
+
+pub use stream::{MergedEvents, StreamingOptions, StreamingSmf};
+
+/// Tempo used until the first tempo event, 120 BPM
+pub const DEFAULT_TEMPO: u32 = 500_000;
+
+/// Errors from parsing a Standard MIDI File
+#[derive(Debug)]
+pub enum SmfError {
+    /// Reading the file failed
+    Io(io::Error),
+    /// The data doesn't start with an `MThd` header
+    NotMidiFile,
+    /// Only formats 0 and 1 are supported
+    UnsupportedFormat(u16),
+    /// The data ended in the middle of a chunk or event
+    UnexpectedEof,
+    /// A data byte appeared where a status byte was needed
+    MissingRunningStatus,
+    /// A status byte that can't appear in a track
+    InvalidStatus(u8),
+}
+
+impl fmt::Display for SmfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SmfError::Io(err) => write!(f, "failed to read MIDI file: {}", err),
+            SmfError::NotMidiFile => write!(f, "not a MIDI file"),
+            SmfError::UnsupportedFormat(format) => {
+                write!(f, "unsupported MIDI file format {}", format)
+            }
+            SmfError::UnexpectedEof => write!(f, "unexpected end of MIDI data"),
+            SmfError::MissingRunningStatus => write!(f, "data byte without a running status"),
+            SmfError::InvalidStatus(status) => {
+                write!(f, "invalid status byte {:#04X} in track", status)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SmfError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SmfError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SmfError {
+    fn from(err: io::Error) -> Self {
+        if err.kind() == io::ErrorKind::UnexpectedEof {
+            SmfError::UnexpectedEof
+        } else {
+            SmfError::Io(err)
+        }
+    }
+}
+
+/// How delta times in the file are measured
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timing {
+    /// Ticks per quarter note, scaled by the tempo
+    TicksPerQuarter(u16),
+    /// Fixed SMPTE time. 29 frames per second means 29.97 (drop frame).
+    Smpte {
+        frames_per_second: u8,
+        ticks_per_frame: u8,
+    },
+}
+
+/// A meta event, `FF <kind> <len> <data>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetaEvent {
+    pub kind: u8,
+    pub data: Vec<u8>,
+}
+
+impl MetaEvent {
+    /// Returns the tempo in microseconds per quarter note for `FF 51` events
+    pub fn tempo(&self) -> Option<u32> {
+        match (self.kind, self.data.as_slice()) {
+            (0x51, [a, b, c]) => Some((*a as u32) << 16 | (*b as u32) << 8 | *c as u32),
+            _ => None,
+        }
+    }
+
+    /// Returns whether this is the `FF 2F` end of track event
+    pub fn is_end_of_track(&self) -> bool {
+        self.kind == 0x2F
+    }
+}
+
+/// The contents of a track event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventKind {
+    /// A channel message, packed like [`ShortMessage::pack`](crate::ShortMessage::pack)
+    Midi(u32),
+    /// An `F0` SysEx event, including the leading `F0`
+    SysEx(Vec<u8>),
+    /// An `F7` escape event, sent as-is
+    Escape(Vec<u8>),
+    /// A meta event
+    Meta(MetaEvent),
+}
+
+/// A single event in a track
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackEvent {
+    /// Ticks since the previous event in the track
+    pub delta: u32,
+    pub kind: EventKind,
+}
+
+/// A single `MTrk` chunk
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Track {
+    pub events: Vec<TrackEvent>,
+}
+
+/// An event from any track, with its absolute position
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimedEvent {
+    /// Ticks since the start of the file
+    pub tick: u64,
+    /// Time since the start of the file, with the tempo map applied
+    pub time: Duration,
+    /// Index of the track the event came from
+    pub track: usize,
+    pub kind: EventKind,
+}
+
+/// A parsed Standard MIDI File (format 0 or 1)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Smf {
+    pub format: u16,
+    pub timing: Timing,
+    pub tracks: Vec<Track>,
+}
+
+impl Smf {
+    /// Reads and parses the file at `path`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, SmfError> {
+        Self::parse(&fs::read(path)?)
+    }
+
+    /// Parses a complete file from `data`
+    pub fn parse(data: &[u8]) -> Result<Self, SmfError> {
+        let mut reader = data;
+        let header = read_header(&mut reader)?;
+
+        let mut tracks = Vec::with_capacity(header.track_count as usize);
+        while tracks.len() < header.track_count as usize {
+            let (id, len) = match read_chunk_header(&mut reader) {
+                Ok(chunk) => chunk,
+                // Some files declare more tracks than they contain
+                Err(SmfError::UnexpectedEof) if reader.is_empty() => break,
+                Err(err) => return Err(err),
+            };
+            // Tolerate a truncated last chunk
+            let len = (len as usize).min(reader.len());
+            let (chunk, rest) = reader.split_at(len);
+            reader = rest;
+
+            if &id != b"MTrk" {
+                continue;
+            }
+
+            let mut chunk = chunk;
+            let mut decoder = TrackDecoder::new();
+            let mut events = Vec::new();
+            while let Some(event) = decoder.next_event(&mut chunk)? {
+                events.push(event);
+            }
+            tracks.push(Track { events });
+        }
+
+        Ok(Smf {
+            format: header.format,
+            timing: header.timing,
+            tracks,
+        })
+    }
+
+    /// Merges every track into one list sorted by time, applying the tempo
+    /// map. Events at the same tick keep their track order.
+    pub fn merged_events(&self) -> Vec<TimedEvent> {
+        let mut events = Vec::with_capacity(self.tracks.iter().map(|t| t.events.len()).sum());
+        for (track, t) in self.tracks.iter().enumerate() {
+            let mut tick = 0;
+            for event in &t.events {
+                tick += event.delta as u64;
+                events.push(TimedEvent {
+                    tick,
+                    time: Duration::ZERO,
+                    track,
+                    kind: event.kind.clone(),
+                });
+            }
+        }
+        events.sort_by_key(|event| (event.tick, event.track));
+
+        let mut tempo = TempoTracker::new(self.timing);
+        for event in &mut events {
+            event.time = tempo.time_at(event.tick);
+            if let EventKind::Meta(meta) = &event.kind {
+                if let Some(t) = meta.tempo() {
+                    tempo.set_tempo(event.tick, t);
+                }
+            }
+        }
+        events
+    }
+
+    /// Collects the tempo changes of every track
+    pub fn tempo_map(&self) -> TempoMap {
+        let mut map = TempoMap::new(self.timing);
+        for event in self.merged_events() {
+            if let EventKind::Meta(meta) = &event.kind {
+                if let Some(tempo) = meta.tempo() {
+                    map.push(event.tick, tempo);
+                }
+            }
+        }
+        map
+    }
+
+    /// Returns the time of the last event, with the tempo map applied
+    pub fn duration(&self) -> Duration {
+        self.merged_events()
+            .last()
+            .map(|event| event.time)
+            .unwrap_or_default()
+    }
+}
+
+pub(crate) struct Header {
+    pub format: u16,
+    pub track_count: u16,
+    pub timing: Timing,
+}
+
+pub(crate) fn read_header(reader: &mut impl Read) -> Result<Header, SmfError> {
+    let mut id = [0; 4];
+    reader
+        .read_exact(&mut id)
+        .map_err(|_| SmfError::NotMidiFile)?;
+    if &id != b"MThd" {
+        return Err(SmfError::NotMidiFile);
+    }
+    let len = read_u32(reader)?;
+    if len < 6 {
+        return Err(SmfError::NotMidiFile);
+    }
+
+    let format = read_u16(reader)?;
+    let track_count = read_u16(reader)?;
+    let division = read_u16(reader)?;
+    io::copy(&mut reader.take(len as u64 - 6), &mut io::sink())?;
+
+    if format > 1 {
+        return Err(SmfError::UnsupportedFormat(format));
+    }
+
+    let timing = if division & 0x8000 == 0 {
+        Timing::TicksPerQuarter(division.max(1))
+    } else {
+        Timing::Smpte {
+            frames_per_second: ((division >> 8) as u8 as i8).unsigned_abs(),
+            ticks_per_frame: (division as u8).max(1),
+        }
+    };
+
+    Ok(Header {
+        format,
+        track_count,
+        timing,
+    })
+}
+
+pub(crate) fn read_chunk_header(reader: &mut impl Read) -> Result<([u8; 4], u32), SmfError> {
+    let mut id = [0; 4];
+    reader.read_exact(&mut id)?;
+    Ok((id, read_u32(reader)?))
+}
+
+fn read_u8(reader: &mut impl Read) -> Result<u8, SmfError> {
+    let mut buf = [0];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u16(reader: &mut impl Read) -> Result<u16, SmfError> {
+    let mut buf = [0; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_be_bytes(buf))
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32, SmfError> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_var_len(reader: &mut impl Read) -> Result<u32, SmfError> {
+    let first = read_u8(reader)?;
+    read_var_len_from(first, reader)
+}
+
+/// Reads a variable-length quantity whose first byte was already read
+fn read_var_len_from(first: u8, reader: &mut impl Read) -> Result<u32, SmfError> {
+    let mut byte = first;
+    let mut value = (byte & 0x7F) as u32;
+    for _ in 1..4 {
+        if byte & 0x80 == 0 {
+            break;
+        }
+        byte = read_u8(reader)?;
+        value = value << 7 | (byte & 0x7F) as u32;
+    }
+    Ok(value)
+}
+
+fn read_vec(reader: &mut impl Read, len: u32) -> Result<Vec<u8>, SmfError> {
+    let mut data = Vec::new();
+    reader.take(len as u64).read_to_end(&mut data)?;
+    if data.len() != len as usize {
+        return Err(SmfError::UnexpectedEof);
+    }
+    Ok(data)
+}
+
+/// Decodes events one at a time from a track chunk, keeping the running
+/// status between calls.
+pub(crate) struct TrackDecoder {
+    running_status: Option<u8>,
+    ended: bool,
+}
+
+impl TrackDecoder {
+    pub fn new() -> Self {
+        TrackDecoder {
+            running_status: None,
+            ended: false,
+        }
+    }
+
+    /// Returns the next event, or `None` at the end of the track
+    pub fn next_event(&mut self, reader: &mut impl Read) -> Result<Option<TrackEvent>, SmfError> {
+        if self.ended {
+            return Ok(None);
+        }
+
+        // A clean end of the chunk is only allowed between events
+        let mut first = [0];
+        if reader.read(&mut first)? == 0 {
+            self.ended = true;
+            return Ok(None);
+        }
+        let delta = read_var_len_from(first[0], reader)?;
+
+        let mut status = read_u8(reader)?;
+        let kind = match status {
+            0xF0 => {
+                let len = read_var_len(reader)?;
+                let mut data = Vec::with_capacity(len as usize + 1);
+                data.push(0xF0);
+                data.extend(read_vec(reader, len)?);
+                EventKind::SysEx(data)
+            }
+            0xF7 => {
+                let len = read_var_len(reader)?;
+                EventKind::Escape(read_vec(reader, len)?)
+            }
+            0xFF => {
+                let kind = read_u8(reader)?;
+                let len = read_var_len(reader)?;
+                let meta = MetaEvent {
+                    kind,
+                    data: read_vec(reader, len)?,
+                };
+                if meta.is_end_of_track() {
+                    self.ended = true;
+                }
+                EventKind::Meta(meta)
+            }
+            0xF1..=0xFE => return Err(SmfError::InvalidStatus(status)),
+            _ => {
+                let data1 = if status & 0x80 == 0 {
+                    let data1 = status;
+                    status = self.running_status.ok_or(SmfError::MissingRunningStatus)?;
+                    data1
+                } else {
+                    self.running_status = Some(status);
+                    read_u8(reader)?
+                };
+                let data2 = match status & 0xF0 {
+                    0xC0 | 0xD0 => 0,
+                    _ => read_u8(reader)?,
+                };
+                EventKind::Midi(status as u32 | (data1 as u32) << 8 | (data2 as u32) << 16)
+            }
+        };
+
+        Ok(Some(TrackEvent { delta, kind }))
+    }
+}
+
+/// Converts between ticks and time for a whole file
+#[derive(Debug, Clone)]
+pub struct TempoMap {
+    /// One tracker per tempo segment, in tick order
+    segments: Vec<TempoTracker>,
+}
+
+impl TempoMap {
+    fn new(timing: Timing) -> Self {
+        TempoMap {
+            segments: vec![TempoTracker::new(timing)],
+        }
+    }
+
+    fn push(&mut self, tick: u64, tempo: u32) {
+        let mut segment = self.segments[self.segments.len() - 1].clone();
+        segment.set_tempo(tick, tempo);
+        self.segments.push(segment);
+    }
+
+    /// Returns the time at `tick`
+    pub fn time_at(&self, tick: u64) -> Duration {
+        let index = self
+            .segments
+            .partition_point(|segment| segment.segment_tick <= tick);
+        self.segments[index.saturating_sub(1)].time_at(tick)
+    }
+
+    /// Returns the last tick at or before `time`
+    pub fn tick_at(&self, time: Duration) -> u64 {
+        let nanos = time.as_nanos();
+        let index = self
+            .segments
+            .partition_point(|segment| segment.segment_nanos <= nanos);
+        self.segments[index.saturating_sub(1)].tick_at(time)
+    }
+}
+
+/// Converts ticks to time, following tempo changes in tick order
+#[derive(Debug, Clone)]
+pub(crate) struct TempoTracker {
+    timing: Timing,
+    tempo: u32,
+    segment_tick: u64,
+    segment_nanos: u128,
+}
+
+impl TempoTracker {
+    pub fn new(timing: Timing) -> Self {
+        TempoTracker {
+            timing,
+            tempo: DEFAULT_TEMPO,
+            segment_tick: 0,
+            segment_nanos: 0,
+        }
+    }
+
+    /// Returns the time at `tick`, which must not be before the last tempo change
+    pub fn time_at(&self, tick: u64) -> Duration {
+        let ticks = tick.saturating_sub(self.segment_tick) as u128;
+        let (numerator, denominator) = self.nanos_per_tick();
+        Duration::from_nanos((self.segment_nanos + ticks * numerator / denominator) as u64)
+    }
+
+    /// Returns the last tick at or before `time`, which must not be before
+    /// the last tempo change
+    pub fn tick_at(&self, time: Duration) -> u64 {
+        let nanos = time.as_nanos().saturating_sub(self.segment_nanos);
+        let (numerator, denominator) = self.nanos_per_tick();
+        self.segment_tick + (nanos * denominator / numerator.max(1)) as u64
+    }
+
+    /// The length of a tick in nanoseconds, as a fraction
+    fn nanos_per_tick(&self) -> (u128, u128) {
+        match self.timing {
+            Timing::TicksPerQuarter(ppq) => (self.tempo as u128 * 1000, ppq as u128),
+            Timing::Smpte {
+                frames_per_second,
+                ticks_per_frame,
+            } => {
+                // 29 is 29.97 frames per second
+                let frames_per_1000s = if frames_per_second == 29 {
+                    29_970
+                } else {
+                    frames_per_second.max(1) as u128 * 1000
+                };
+                (
+                    1_000_000_000_000,
+                    frames_per_1000s * ticks_per_frame as u128,
+                )
+            }
+        }
+    }
+
+    /// Applies a tempo change at `tick`
+    pub fn set_tempo(&mut self, tick: u64, tempo: u32) {
+        self.segment_nanos = self.time_at(tick).as_nanos();
+        self.segment_tick = tick;
+        self.tempo = tempo;
+    }
+}
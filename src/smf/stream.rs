@@ -0,0 +1,389 @@
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, SyncSender, TryRecvError, TrySendError},
+    thread::{self, JoinHandle, Thread},
+    time::Duration,
+    vec,
+};
+
+use super::{
+    read_chunk_header, read_header, EventKind, SmfError, TempoTracker, TimedEvent, Timing,
+    TrackDecoder,
+};
+
+/// How long an idle worker sleeps before checking its tracks again, in case
+/// an unpark was missed
+const WORKER_PARK_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// Tuning for [`StreamingSmf::events`]
+#[derive(Debug, Clone)]
+pub struct StreamingOptions {
+    /// Number of decoding threads. Tracks are spread evenly across them.
+    pub workers: usize,
+    /// Events decoded per batch
+    pub batch_size: usize,
+    /// Decoded batches buffered per track, bounding memory use
+    pub buffered_batches: usize,
+    /// Bytes of raw track data read from the file at a time, per track
+    pub read_buffer_size: usize,
+}
+
+impl Default for StreamingOptions {
+    fn default() -> Self {
+        StreamingOptions {
+            workers: thread::available_parallelism().map_or(4, |n| n.get()),
+            batch_size: 1024,
+            buffered_batches: 2,
+            read_buffer_size: 16 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TrackChunk {
+    offset: u64,
+    len: u64,
+}
+
+/// A Standard MIDI File that is decoded lazily while it's being played,
+/// for files too large to hold in memory.
+///
+/// Opening only reads the header and the position of each track.
+/// [`events`](Self::events) then decodes the tracks on worker threads into
+/// bounded buffers and merges them in time order.
+#[derive(Debug, Clone)]
+pub struct StreamingSmf {
+    path: PathBuf,
+    format: u16,
+    timing: Timing,
+    tracks: Vec<TrackChunk>,
+}
+
+impl StreamingSmf {
+    /// Reads the header and track positions of the file at `path`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, SmfError> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = File::open(&path)?;
+        let file_len = file.metadata()?.len();
+        let header = read_header(&mut file)?;
+
+        let mut tracks = Vec::with_capacity(header.track_count as usize);
+        let mut offset = file.stream_position()?;
+        while tracks.len() < header.track_count as usize && offset + 8 <= file_len {
+            let (id, len) = read_chunk_header(&mut file)?;
+            offset += 8;
+            let len = (len as u64).min(file_len - offset);
+            if &id == b"MTrk" {
+                tracks.push(TrackChunk { offset, len });
+            }
+            offset += len;
+            file.seek(SeekFrom::Start(offset))?;
+        }
+
+        Ok(StreamingSmf {
+            path,
+            format: header.format,
+            timing: header.timing,
+            tracks,
+        })
+    }
+
+    /// Returns the file format, 0 or 1
+    pub fn format(&self) -> u16 {
+        self.format
+    }
+
+    /// Returns how delta times in the file are measured
+    pub fn timing(&self) -> Timing {
+        self.timing
+    }
+
+    /// Returns the number of tracks found in the file
+    pub fn track_count(&self) -> usize {
+        self.tracks.len()
+    }
+
+    /// Starts decoding every track and returns their events merged in time
+    /// order, with the tempo map applied.
+    pub fn events(&self, options: &StreamingOptions) -> Result<MergedEvents, SmfError> {
+        let worker_count = options.workers.clamp(1, self.tracks.len().max(1));
+        let batch_size = options.batch_size.max(1);
+
+        let mut receivers = Vec::with_capacity(self.tracks.len());
+        let mut assignments: Vec<Vec<TrackWork>> = (0..worker_count).map(|_| Vec::new()).collect();
+        for (index, chunk) in self.tracks.iter().enumerate() {
+            let (sender, receiver) = mpsc::sync_channel(options.buffered_batches.max(1));
+            receivers.push(receiver);
+            assignments[index % worker_count].push(TrackWork {
+                buffer: TrackBuffer {
+                    offset: chunk.offset,
+                    remaining: chunk.len,
+                    data: Vec::with_capacity(options.read_buffer_size.max(16)),
+                    pos: 0,
+                    capacity: options.read_buffer_size.max(16),
+                },
+                decoder: TrackDecoder::new(),
+                tick: 0,
+                sender: Some(sender),
+                pending: None,
+            });
+        }
+
+        let mut workers = Vec::with_capacity(worker_count);
+        for tracks in assignments {
+            let file = File::open(&self.path)?;
+            workers.push(thread::spawn(move || run_worker(file, tracks, batch_size)));
+        }
+
+        let tracks = receivers
+            .into_iter()
+            .enumerate()
+            .map(|(index, receiver)| MergedTrack {
+                receiver: Some(receiver),
+                batch: Vec::new().into_iter(),
+                worker: workers[index % worker_count].thread().clone(),
+            })
+            .collect();
+
+        let mut merged = MergedEvents {
+            tracks,
+            heads: Vec::new(),
+            heap: BinaryHeap::new(),
+            tempo: TempoTracker::new(self.timing),
+            errors: Vec::new(),
+            workers,
+        };
+        merged.prime();
+        Ok(merged)
+    }
+}
+
+type Batch = Result<Vec<(u64, EventKind)>, SmfError>;
+
+/// Raw track bytes, read from the file a block at a time
+struct TrackBuffer {
+    offset: u64,
+    remaining: u64,
+    data: Vec<u8>,
+    pos: usize,
+    capacity: usize,
+}
+
+struct TrackReader<'a> {
+    file: &'a mut File,
+    buffer: &'a mut TrackBuffer,
+}
+
+impl Read for TrackReader<'_> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let buffer = &mut *self.buffer;
+        if buffer.pos == buffer.data.len() {
+            if buffer.remaining == 0 {
+                return Ok(0);
+            }
+            let len = (buffer.capacity as u64).min(buffer.remaining) as usize;
+            buffer.data.resize(len, 0);
+            self.file.seek(SeekFrom::Start(buffer.offset))?;
+            self.file.read_exact(&mut buffer.data)?;
+            buffer.offset += len as u64;
+            buffer.remaining -= len as u64;
+            buffer.pos = 0;
+        }
+
+        let len = out.len().min(buffer.data.len() - buffer.pos);
+        out[..len].copy_from_slice(&buffer.data[buffer.pos..buffer.pos + len]);
+        buffer.pos += len;
+        Ok(len)
+    }
+}
+
+struct TrackWork {
+    buffer: TrackBuffer,
+    decoder: TrackDecoder,
+    tick: u64,
+    /// `None` once the track is finished or the consumer is gone
+    sender: Option<SyncSender<Batch>>,
+    /// A decoded batch that didn't fit in the channel yet
+    pending: Option<Batch>,
+}
+
+impl TrackWork {
+    /// Decodes up to `batch_size` events. An empty batch means the track ended.
+    fn decode(&mut self, file: &mut File, batch_size: usize) -> Batch {
+        let mut reader = TrackReader {
+            file,
+            buffer: &mut self.buffer,
+        };
+        let mut events = Vec::with_capacity(batch_size);
+        while events.len() < batch_size {
+            match self.decoder.next_event(&mut reader)? {
+                Some(event) => {
+                    self.tick += event.delta as u64;
+                    events.push((self.tick, event.kind));
+                }
+                None => break,
+            }
+        }
+        Ok(events)
+    }
+}
+
+fn run_worker(mut file: File, mut tracks: Vec<TrackWork>, batch_size: usize) {
+    loop {
+        let mut progressed = false;
+        let mut active = false;
+
+        for track in &mut tracks {
+            if track.sender.is_none() {
+                continue;
+            }
+            active = true;
+
+            let batch = match track.pending.take() {
+                Some(batch) => batch,
+                None => track.decode(&mut file, batch_size),
+            };
+            let finished = !matches!(&batch, Ok(events) if !events.is_empty());
+
+            let result = match &track.sender {
+                Some(sender) => sender.try_send(batch),
+                None => continue,
+            };
+            match result {
+                Ok(()) => {
+                    progressed = true;
+                    if finished {
+                        track.sender = None;
+                    }
+                }
+                Err(TrySendError::Full(batch)) => track.pending = Some(batch),
+                Err(TrySendError::Disconnected(_)) => track.sender = None,
+            }
+        }
+
+        if !active {
+            break;
+        }
+        if !progressed {
+            thread::park_timeout(WORKER_PARK_TIMEOUT);
+        }
+    }
+}
+
+struct MergedTrack {
+    receiver: Option<Receiver<Batch>>,
+    batch: vec::IntoIter<(u64, EventKind)>,
+    worker: Thread,
+}
+
+impl MergedTrack {
+    /// Returns the track's next event, blocking until it's decoded
+    fn next(&mut self) -> Option<Result<(u64, EventKind), SmfError>> {
+        loop {
+            if let Some(event) = self.batch.next() {
+                return Some(Ok(event));
+            }
+
+            let receiver = self.receiver.as_ref()?;
+            let batch = match receiver.try_recv() {
+                Ok(batch) => Ok(batch),
+                Err(TryRecvError::Empty) => {
+                    self.worker.unpark();
+                    receiver.recv()
+                }
+                Err(TryRecvError::Disconnected) => Err(mpsc::RecvError),
+            };
+            // Room was made in the channel
+            self.worker.unpark();
+
+            match batch {
+                Ok(Ok(events)) if !events.is_empty() => self.batch = events.into_iter(),
+                Ok(Err(err)) => {
+                    self.receiver = None;
+                    return Some(Err(err));
+                }
+                _ => {
+                    self.receiver = None;
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+/// The merged events of a [`StreamingSmf`], in time order.
+///
+/// Events at the same tick keep their track order. If a track fails to
+/// decode, the error is yielded once and the rest of that track is skipped.
+pub struct MergedEvents {
+    tracks: Vec<MergedTrack>,
+    heads: Vec<Option<EventKind>>,
+    heap: BinaryHeap<Reverse<(u64, usize)>>,
+    tempo: TempoTracker,
+    errors: Vec<SmfError>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl MergedEvents {
+    fn prime(&mut self) {
+        self.heads = (0..self.tracks.len()).map(|_| None).collect();
+        for index in 0..self.tracks.len() {
+            self.advance(index);
+        }
+    }
+
+    /// Pulls the next event of a track into the heap
+    fn advance(&mut self, index: usize) {
+        match self.tracks[index].next() {
+            Some(Ok((tick, kind))) => {
+                self.heads[index] = Some(kind);
+                self.heap.push(Reverse((tick, index)));
+            }
+            Some(Err(err)) => self.errors.push(err),
+            None => {}
+        }
+    }
+}
+
+impl Iterator for MergedEvents {
+    type Item = Result<TimedEvent, SmfError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(err) = self.errors.pop() {
+            return Some(Err(err));
+        }
+
+        let Reverse((tick, track)) = self.heap.pop()?;
+        let kind = self.heads[track].take()?;
+        self.advance(track);
+
+        let time = self.tempo.time_at(tick);
+        if let EventKind::Meta(meta) = &kind {
+            if let Some(tempo) = meta.tempo() {
+                self.tempo.set_tempo(tick, tempo);
+            }
+        }
+
+        Some(Ok(TimedEvent {
+            tick,
+            time,
+            track,
+            kind,
+        }))
+    }
+}
+
+impl Drop for MergedEvents {
+    fn drop(&mut self) {
+        // Disconnect the channels so the workers stop
+        self.tracks.clear();
+        for worker in self.workers.drain(..) {
+            worker.thread().unpark();
+            let _ = worker.join();
+        }
+    }
+}
@@ -0,0 +1,499 @@
+use std::{
+    collections::BTreeMap,
+    env, fmt,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+mod sf2;
+mod sfz;
+
+pub use sf2::{Sf2, Sf2Error, Sf2Preset};
+pub use sfz::{Sfz, SfzProblem, SfzProblemKind};
+
+/// Errors from parsing an OmniMIDI soundfont list
+#[derive(Debug)]
+pub enum SoundFontListError {
+    /// Reading the file failed
+    Io(io::Error),
+    /// A line that isn't `key = value` inside an `sf.start` ... `sf.end` block
+    InvalidLine { line: usize },
+    /// A setting whose value can't be parsed
+    InvalidValue { line: usize, key: String },
+    /// An `sf.start` without a matching `sf.end`, or the other way around
+    UnbalancedBlock { line: usize },
+    /// An entry without an `sf.path`
+    MissingPath { line: usize },
+}
+
+impl fmt::Display for SoundFontListError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SoundFontListError::Io(err) => write!(f, "failed to read soundfont list: {}", err),
+            SoundFontListError::InvalidLine { line } => {
+                write!(f, "invalid soundfont list line {}", line)
+            }
+            SoundFontListError::InvalidValue { line, key } => {
+                write!(
+                    f,
+                    "invalid value for {} on soundfont list line {}",
+                    key, line
+                )
+            }
+            SoundFontListError::UnbalancedBlock { line } => {
+                write!(
+                    f,
+                    "unmatched sf.start or sf.end on soundfont list line {}",
+                    line
+                )
+            }
+            SoundFontListError::MissingPath { line } => {
+                write!(
+                    f,
+                    "soundfont list entry ending on line {} has no sf.path",
+                    line
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for SoundFontListError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SoundFontListError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SoundFontListError {
+    fn from(err: io::Error) -> Self {
+        SoundFontListError::Io(err)
+    }
+}
+
+/// One soundfont in a [`SoundFontList`].
+///
+/// Bank and preset overrides of `None` are written as `-1`, which OmniMIDI
+/// reads as "all banks" or "all presets".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SoundFontEntry {
+    /// The `.sf2`, `.sf3` or `.sfz` file
+    pub path: PathBuf,
+    pub enabled: bool,
+    /// Load every sample when the list is loaded, instead of on first use
+    pub preload: bool,
+    /// Only use this bank from the soundfont (`sf.srcb`), 0 to 128
+    pub source_bank: Option<u8>,
+    /// Only use this preset from the soundfont (`sf.srcp`)
+    pub source_preset: Option<u8>,
+    /// Map the source bank to this bank (`sf.desb`), 0 to 128
+    pub destination_bank: Option<u8>,
+    /// Map the source preset to this preset (`sf.desp`)
+    pub destination_preset: Option<u8>,
+    /// Use the soundfont's drum kits for XG drum channels (`sf.xgdrums`)
+    pub xg_drums: bool,
+}
+
+impl SoundFontEntry {
+    /// Creates an enabled entry for `path` without overrides, with the same
+    /// defaults as OmniMIDI's configurator
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        SoundFontEntry {
+            path: path.into(),
+            enabled: true,
+            preload: false,
+            source_bank: None,
+            source_preset: None,
+            destination_bank: Some(0),
+            destination_preset: None,
+            xg_drums: false,
+        }
+    }
+
+    /// Returns whether the path has an `.sfz` extension
+    pub fn is_sfz(&self) -> bool {
+        self.path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("sfz"))
+    }
+
+    /// Returns the bank and program a preset of the soundfont ends up at
+    /// after the overrides, or `None` if the overrides leave it out.
+    ///
+    /// Follows BASSMIDI's rules: without a source bank, the destination
+    /// bank is added to every bank as an offset.
+    pub fn map_preset(&self, bank: u16, program: u16) -> Option<(u16, u16)> {
+        let matches =
+            |filter: Option<u8>, value: u16| filter.map_or(true, |v| u16::from(v) == value);
+        if !matches(self.source_bank, bank) || !matches(self.source_preset, program) {
+            return None;
+        }
+        let bank = match (self.source_bank, self.destination_bank) {
+            (_, None) => bank,
+            (Some(_), Some(destination)) => u16::from(destination),
+            (None, Some(offset)) => bank.saturating_add(u16::from(offset)),
+        };
+        let program = self.destination_preset.map_or(program, u16::from);
+        Some((bank, program))
+    }
+}
+
+/// An OmniMIDI soundfont list, as loaded by
+/// [`KDMAPIStream::load_soundfont_list`](crate::KDMAPIStream::load_soundfont_list).
+///
+/// Each entry is a block of settings:
+///
+/// ```text
+/// sf.start
+/// sf.path = C:\SoundFonts\piano.sf2
+/// sf.enabled = 1
+/// sf.preload = 0
+/// sf.srcb = -1
+/// sf.srcp = -1
+/// sf.desb = 0
+/// sf.desp = -1
+/// sf.xgdrums = 0
+/// sf.end
+/// ```
+///
+/// Lists from older OmniMIDI versions, with one path per line, are read as
+/// well; a path starting with `@` is a disabled entry. Entries are kept in
+/// the order of the file, which OmniMIDI uses as their priority.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SoundFontList {
+    pub entries: Vec<SoundFontEntry>,
+}
+
+impl SoundFontList {
+    /// Creates an empty list
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads and parses the list file at `path`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, SoundFontListError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parses a list from its text
+    pub fn parse(text: &str) -> Result<Self, SoundFontListError> {
+        let mut entries = Vec::new();
+        // The entry being read, and the line of its `sf.start`
+        let mut block: Option<(SoundFontEntry, bool, usize)> = None;
+
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            match (line, &mut block) {
+                ("sf.start", None) => {
+                    block = Some((SoundFontEntry::new(PathBuf::new()), false, line_number))
+                }
+                ("sf.start", Some(_)) => {
+                    return Err(SoundFontListError::UnbalancedBlock { line: line_number })
+                }
+                ("sf.end", None) => {
+                    return Err(SoundFontListError::UnbalancedBlock { line: line_number })
+                }
+                ("sf.end", Some(_)) => {
+                    let (entry, has_path, _) = block.take().unwrap();
+                    if !has_path {
+                        return Err(SoundFontListError::MissingPath { line: line_number });
+                    }
+                    entries.push(entry);
+                }
+                (_, Some((entry, has_path, _))) => {
+                    parse_setting(entry, has_path, line, line_number)?;
+                }
+                (_, None) => {
+                    let (enabled, path) = match line.strip_prefix('@') {
+                        Some(path) => (false, path),
+                        None => (true, line),
+                    };
+                    let mut entry = SoundFontEntry::new(path);
+                    entry.enabled = enabled;
+                    entries.push(entry);
+                }
+            }
+        }
+
+        match block {
+            Some((_, _, line)) => Err(SoundFontListError::UnbalancedBlock { line }),
+            None => Ok(SoundFontList { entries }),
+        }
+    }
+
+    /// Serializes the list in the block format.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidData`] if a path isn't valid
+    /// UTF-8, as the format has no way to represent it.
+    pub fn to_text(&self) -> io::Result<String> {
+        let override_value = |value: Option<u8>| value.map_or(-1, i16::from);
+        let mut text = String::new();
+        for entry in &self.entries {
+            let path = entry.path.to_str().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("soundfont path {:?} isn't valid UTF-8", entry.path),
+                )
+            })?;
+            text += &format!(
+                "sf.start\n\
+                 sf.path = {}\n\
+                 sf.enabled = {}\n\
+                 sf.preload = {}\n\
+                 sf.srcb = {}\n\
+                 sf.srcp = {}\n\
+                 sf.desb = {}\n\
+                 sf.desp = {}\n\
+                 sf.xgdrums = {}\n\
+                 sf.end\n",
+                path,
+                entry.enabled as u8,
+                entry.preload as u8,
+                override_value(entry.source_bank),
+                override_value(entry.source_preset),
+                override_value(entry.destination_bank),
+                override_value(entry.destination_preset),
+                entry.xg_drums as u8,
+            );
+        }
+        Ok(text)
+    }
+
+    /// Writes the list to `path`, in the block format
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_text()?)
+    }
+
+    /// Writes the list to a new file in the temp directory and returns its
+    /// path
+    pub(crate) fn save_temp(&self) -> io::Result<PathBuf> {
+        static NEXT_ID: AtomicU32 = AtomicU32::new(0);
+        let text = self.to_text()?;
+        loop {
+            let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+            let path = env::temp_dir().join(format!("kdmapi-{}-{}.sflist", process::id(), id));
+            // Never reuse an existing file, which could be a link planted
+            // by another user of the temp directory
+            let mut file = match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => file,
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            };
+            if let Err(err) = file.write_all(text.as_bytes()) {
+                let _ = fs::remove_file(&path);
+                return Err(err);
+            }
+            return Ok(path);
+        }
+    }
+
+    /// Inspects the soundfont files of the enabled entries, reporting
+    /// missing or broken files and presets that are hidden by an earlier
+    /// entry.
+    ///
+    /// SF2 files provide their presets through the entry's overrides. An
+    /// SFZ file is a single instrument, placed at the destination bank and
+    /// preset (0 when not set).
+    pub fn check(&self) -> Vec<ListIssue> {
+        let mut issues = Vec::new();
+        // Which entry provides each (bank, program) so far
+        let mut providers = BTreeMap::new();
+
+        for (index, entry) in self.entries.iter().enumerate() {
+            if !entry.enabled {
+                continue;
+            }
+            let presets = match entry_presets(index, entry) {
+                Ok(presets) => presets,
+                Err(issue) => {
+                    issues.push(issue);
+                    continue;
+                }
+            };
+            if presets.is_empty() {
+                issues.push(ListIssue::NoPresets { entry: index });
+                continue;
+            }
+
+            let mut shadowed: BTreeMap<usize, Vec<(u16, u16)>> = BTreeMap::new();
+            for preset in presets {
+                match providers.get(&preset) {
+                    Some(&by) => shadowed.entry(by).or_default().push(preset),
+                    None => {
+                        providers.insert(preset, index);
+                    }
+                }
+            }
+            issues.extend(
+                shadowed
+                    .into_iter()
+                    .map(|(by, presets)| ListIssue::Shadowed {
+                        entry: index,
+                        by,
+                        presets,
+                    }),
+            );
+        }
+        issues
+    }
+}
+
+fn parse_setting(
+    entry: &mut SoundFontEntry,
+    has_path: &mut bool,
+    line: &str,
+    line_number: usize,
+) -> Result<(), SoundFontListError> {
+    let (key, value) = line
+        .split_once('=')
+        .ok_or(SoundFontListError::InvalidLine { line: line_number })?;
+    let (key, value) = (key.trim(), value.trim());
+    let invalid = || SoundFontListError::InvalidValue {
+        line: line_number,
+        key: key.to_owned(),
+    };
+
+    let flag = || match value {
+        "1" | "true" => Ok(true),
+        "0" | "false" => Ok(false),
+        _ => Err(invalid()),
+    };
+    // Bank 128 holds the drum kits in SF2 files
+    let override_value = |max: i16| match value.parse::<i16>() {
+        Ok(-1) => Ok(None),
+        Ok(value) if (0..=max).contains(&value) => Ok(Some(value as u8)),
+        _ => Err(invalid()),
+    };
+
+    match key {
+        "sf.path" => {
+            entry.path = PathBuf::from(value);
+            *has_path = true;
+        }
+        "sf.enabled" => entry.enabled = flag()?,
+        "sf.preload" => entry.preload = flag()?,
+        "sf.srcb" => entry.source_bank = override_value(128)?,
+        "sf.srcp" => entry.source_preset = override_value(127)?,
+        "sf.desb" => entry.destination_bank = override_value(128)?,
+        "sf.desp" => entry.destination_preset = override_value(127)?,
+        "sf.xgdrums" => entry.xg_drums = flag()?,
+        // Settings from newer OmniMIDI versions
+        _ => {}
+    }
+    Ok(())
+}
+
+/// A problem found by [`SoundFontList::check`]. `entry` is an index into
+/// [`SoundFontList::entries`].
+#[derive(Debug)]
+pub enum ListIssue {
+    /// The soundfont file doesn't exist
+    MissingFile { entry: usize },
+    /// The soundfont file exists but can't be read
+    Unreadable { entry: usize, error: io::Error },
+    /// The file isn't a valid SF2 file
+    InvalidSf2 { entry: usize, error: Sf2Error },
+    /// The SFZ file has problems, such as missing samples
+    InvalidSfz {
+        entry: usize,
+        problems: Vec<SfzProblem>,
+    },
+    /// The overrides leave none of the soundfont's presets, or it has none
+    NoPresets { entry: usize },
+    /// `entry` provides presets that an earlier entry, `by`, already
+    /// provides, so they're never used
+    Shadowed {
+        entry: usize,
+        by: usize,
+        /// The hidden presets as (bank, program), after the overrides
+        presets: Vec<(u16, u16)>,
+    },
+}
+
+impl fmt::Display for ListIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListIssue::MissingFile { entry } => write!(f, "entry {}: file doesn't exist", entry),
+            ListIssue::Unreadable { entry, error } => {
+                write!(f, "entry {}: failed to read file: {}", entry, error)
+            }
+            ListIssue::InvalidSf2 { entry, error } => write!(f, "entry {}: {}", entry, error),
+            ListIssue::InvalidSfz { entry, problems } => {
+                write!(
+                    f,
+                    "entry {}: SFZ file has {} problems",
+                    entry,
+                    problems.len()
+                )?;
+                for problem in problems {
+                    write!(f, "\n  {}", problem)?;
+                }
+                Ok(())
+            }
+            ListIssue::NoPresets { entry } => write!(f, "entry {}: provides no presets", entry),
+            ListIssue::Shadowed { entry, by, presets } => write!(
+                f,
+                "entry {}: {} presets are hidden by entry {}",
+                entry,
+                presets.len(),
+                by
+            ),
+        }
+    }
+}
+
+/// Returns the presets an entry provides, sorted and after the overrides
+fn entry_presets(index: usize, entry: &SoundFontEntry) -> Result<Vec<(u16, u16)>, ListIssue> {
+    if !entry.path.exists() {
+        return Err(ListIssue::MissingFile { entry: index });
+    }
+
+    let mut presets = if entry.is_sfz() {
+        let sfz = Sfz::open(&entry.path).map_err(|error| ListIssue::Unreadable {
+            entry: index,
+            error,
+        })?;
+        if !sfz.problems.is_empty() {
+            return Err(ListIssue::InvalidSfz {
+                entry: index,
+                problems: sfz.problems,
+            });
+        }
+        if sfz.regions == 0 {
+            Vec::new()
+        } else {
+            vec![(
+                entry.destination_bank.map_or(0, u16::from),
+                entry.destination_preset.map_or(0, u16::from),
+            )]
+        }
+    } else {
+        let sf2 = Sf2::open(&entry.path).map_err(|error| match error {
+            Sf2Error::Io(error) => ListIssue::Unreadable {
+                entry: index,
+                error,
+            },
+            error => ListIssue::InvalidSf2 {
+                entry: index,
+                error,
+            },
+        })?;
+        sf2.presets
+            .iter()
+            .filter_map(|preset| entry.map_preset(preset.bank, preset.program))
+            .collect()
+    };
+    presets.sort_unstable();
+    presets.dedup();
+    Ok(presets)
+}
@@ -0,0 +1,208 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufReader, Cursor, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+/// Size of a `phdr` record
+const PRESET_HEADER_LEN: u32 = 38;
+
+/// Errors from reading an SF2 file
+#[derive(Debug)]
+pub enum Sf2Error {
+    /// Reading the file failed
+    Io(io::Error),
+    /// The data doesn't start with a `RIFF` `sfbk` header
+    NotSoundFont,
+    /// The data ended in the middle of a chunk
+    UnexpectedEof,
+    /// The file has no `pdta` list with preset headers
+    MissingPresets,
+}
+
+impl fmt::Display for Sf2Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Sf2Error::Io(err) => write!(f, "failed to read SF2 file: {}", err),
+            Sf2Error::NotSoundFont => write!(f, "not an SF2 file"),
+            Sf2Error::UnexpectedEof => write!(f, "unexpected end of SF2 data"),
+            Sf2Error::MissingPresets => write!(f, "SF2 file has no preset headers"),
+        }
+    }
+}
+
+impl std::error::Error for Sf2Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Sf2Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Sf2Error {
+    fn from(err: io::Error) -> Self {
+        if err.kind() == io::ErrorKind::UnexpectedEof {
+            Sf2Error::UnexpectedEof
+        } else {
+            Sf2Error::Io(err)
+        }
+    }
+}
+
+/// A preset header from an SF2 file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sf2Preset {
+    pub name: String,
+    /// MIDI bank, 128 for drum kits
+    pub bank: u16,
+    /// MIDI program
+    pub program: u16,
+}
+
+/// The headers of an SF2 (or SF3) file.
+///
+/// Only the `INFO` list and the preset headers are read; sample data is
+/// skipped over, so inspecting even large soundfonts is cheap.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sf2 {
+    /// Format version from the `ifil` chunk, as (major, minor)
+    pub version: (u16, u16),
+    /// Soundfont name from the `INAM` chunk
+    pub name: String,
+    /// Presets in the order of the file
+    pub presets: Vec<Sf2Preset>,
+}
+
+impl Sf2 {
+    /// Reads the headers of the file at `path`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Sf2Error> {
+        Self::read(&mut BufReader::new(File::open(path)?))
+    }
+
+    /// Reads the headers from a complete file in memory
+    pub fn parse(data: &[u8]) -> Result<Self, Sf2Error> {
+        Self::read(&mut Cursor::new(data))
+    }
+
+    /// Reads the headers from `reader`, seeking past the sample data
+    pub fn read<R: Read + Seek>(reader: &mut R) -> Result<Self, Sf2Error> {
+        let (id, riff_len) = read_chunk_header(reader)?;
+        let mut form = [0; 4];
+        reader.read_exact(&mut form)?;
+        if &id != b"RIFF" || &form != b"sfbk" {
+            return Err(Sf2Error::NotSoundFont);
+        }
+
+        let mut sf2 = Sf2 {
+            version: (0, 0),
+            name: String::new(),
+            presets: Vec::new(),
+        };
+        let mut found_presets = false;
+
+        // Top-level LIST chunks, each holding sub-chunks
+        let mut remaining = (riff_len as u64).saturating_sub(4);
+        while remaining >= 8 {
+            let (id, len) = read_chunk_header(reader)?;
+            let padded = padded_len(len);
+            remaining = remaining.saturating_sub(8 + padded);
+            if &id != b"LIST" || len < 4 {
+                reader.seek(SeekFrom::Current(padded as i64))?;
+                continue;
+            }
+
+            let mut list = [0; 4];
+            reader.read_exact(&mut list)?;
+            let mut list_remaining = padded - 4;
+            if &list == b"sdta" {
+                reader.seek(SeekFrom::Current(list_remaining as i64))?;
+                continue;
+            }
+
+            while list_remaining >= 8 {
+                let (id, len) = read_chunk_header(reader)?;
+                let padded = padded_len(len);
+                list_remaining = list_remaining.saturating_sub(8 + padded);
+                match (&list, &id) {
+                    (b"INFO", b"ifil") if len >= 4 => {
+                        let data = read_vec(reader, padded)?;
+                        sf2.version = (read_u16(&data[0..]), read_u16(&data[2..]));
+                    }
+                    (b"INFO", b"INAM") => {
+                        sf2.name = read_name(&read_vec(reader, padded)?);
+                    }
+                    (b"pdta", b"phdr") => {
+                        let data = read_vec(reader, padded)?;
+                        let records = data[..len as usize].chunks_exact(PRESET_HEADER_LEN as usize);
+                        // The last record only terminates the list
+                        let count = records.len().saturating_sub(1);
+                        sf2.presets = records
+                            .take(count)
+                            .map(|record| Sf2Preset {
+                                name: read_name(&record[..20]),
+                                program: read_u16(&record[20..]),
+                                bank: read_u16(&record[22..]),
+                            })
+                            .collect();
+                        found_presets = true;
+                    }
+                    _ => {
+                        reader.seek(SeekFrom::Current(padded as i64))?;
+                    }
+                }
+            }
+            // Skip a sub-chunk that claimed more than the list holds
+            reader.seek(SeekFrom::Current(list_remaining as i64))?;
+        }
+
+        if !found_presets {
+            return Err(Sf2Error::MissingPresets);
+        }
+        Ok(sf2)
+    }
+
+    /// Returns the preset for `bank` and `program`, if the file has one
+    pub fn preset(&self, bank: u16, program: u16) -> Option<&Sf2Preset> {
+        self.presets
+            .iter()
+            .find(|preset| preset.bank == bank && preset.program == program)
+    }
+}
+
+fn read_chunk_header(reader: &mut impl Read) -> Result<([u8; 4], u32), Sf2Error> {
+    let mut header = [0; 8];
+    reader.read_exact(&mut header)?;
+    let mut id = [0; 4];
+    id.copy_from_slice(&header[..4]);
+    let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    Ok((id, len))
+}
+
+/// Chunks are padded to an even length
+fn padded_len(len: u32) -> u64 {
+    len as u64 + (len & 1) as u64
+}
+
+fn read_vec(reader: &mut impl Read, len: u64) -> Result<Vec<u8>, Sf2Error> {
+    let mut data = Vec::new();
+    reader.take(len).read_to_end(&mut data)?;
+    if data.len() != len as usize {
+        return Err(Sf2Error::UnexpectedEof);
+    }
+    Ok(data)
+}
+
+fn read_u16(data: &[u8]) -> u16 {
+    u16::from_le_bytes([data[0], data[1]])
+}
+
+/// Decodes a NUL-padded name, replacing invalid UTF-8
+fn read_name(data: &[u8]) -> String {
+    let end = data
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).trim_end().to_owned()
+}
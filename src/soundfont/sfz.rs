@@ -0,0 +1,264 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+/// How deep `#include`s can nest, to stop include cycles
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Headers defined by the SFZ format
+const HEADERS: &[&str] = &[
+    "control", "global", "master", "group", "region", "curve", "effect", "midi", "sample",
+];
+
+/// What's wrong in an [`SfzProblem`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SfzProblemKind {
+    /// A `sample` opcode names a file that doesn't exist
+    MissingSample(PathBuf),
+    /// An `#include` names a file that can't be read
+    MissingInclude(PathBuf),
+    /// `#include`s nested more than 16 levels deep, usually a cycle
+    IncludeTooDeep,
+    /// A `<...>` header that isn't part of the SFZ format
+    UnknownHeader(String),
+    /// A `<` without a closing `>`
+    UnterminatedHeader,
+    /// Text that isn't a header, an `opcode=value` pair or a directive
+    UnexpectedText(String),
+}
+
+/// A problem found by [`Sfz::open`], with where it was found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SfzProblem {
+    /// The SFZ file, or the included file the problem is in
+    pub file: PathBuf,
+    pub line: usize,
+    pub kind: SfzProblemKind,
+}
+
+impl fmt::Display for SfzProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: ", self.file.display(), self.line)?;
+        match &self.kind {
+            SfzProblemKind::MissingSample(path) => {
+                write!(f, "sample {} doesn't exist", path.display())
+            }
+            SfzProblemKind::MissingInclude(path) => {
+                write!(f, "included file {} can't be read", path.display())
+            }
+            SfzProblemKind::IncludeTooDeep => write!(f, "includes are nested too deeply"),
+            SfzProblemKind::UnknownHeader(name) => write!(f, "unknown header <{}>", name),
+            SfzProblemKind::UnterminatedHeader => write!(f, "header without a closing >"),
+            SfzProblemKind::UnexpectedText(text) => write!(f, "unexpected text {:?}", text),
+        }
+    }
+}
+
+/// The result of checking an SFZ file.
+///
+/// This is a basic check of the file's structure and the files it refers
+/// to, not a full SFZ parser: opcodes other than `sample` and
+/// `default_path` aren't interpreted. Built-in samples such as `*sine`
+/// aren't checked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sfz {
+    /// Number of `<region>` headers, including those in included files
+    pub regions: usize,
+    /// Every sample file referred to, resolved against the SFZ file's
+    /// directory
+    pub samples: BTreeSet<PathBuf>,
+    /// Problems in the order they were found
+    pub problems: Vec<SfzProblem>,
+}
+
+impl Sfz {
+    /// Reads and checks the SFZ file at `path`, following `#include`s
+    ///
+    /// Only fails if `path` itself can't be read; everything else is
+    /// reported in [`problems`](Self::problems).
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let mut checker = Checker {
+            dir: path.parent().unwrap_or(Path::new("")).to_path_buf(),
+            default_path: String::new(),
+            defines: HashMap::new(),
+            sfz: Sfz {
+                regions: 0,
+                samples: BTreeSet::new(),
+                problems: Vec::new(),
+            },
+        };
+        checker.check(path, &text, 0);
+        Ok(checker.sfz)
+    }
+
+    /// Returns whether no problems were found and there's at least one
+    /// region
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty() && self.regions > 0
+    }
+}
+
+struct Checker {
+    /// Directory of the top-level file, which paths are relative to
+    dir: PathBuf,
+    default_path: String,
+    defines: HashMap<String, String>,
+    sfz: Sfz,
+}
+
+impl Checker {
+    fn check(&mut self, file: &Path, text: &str, depth: usize) {
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let mut problems = Vec::new();
+            let line = match line.find("//") {
+                Some(comment) => &line[..comment],
+                None => line,
+            }
+            .trim();
+
+            if let Some(directive) = line.strip_prefix('#') {
+                if let Some(kind) = self.directive(directive, depth) {
+                    self.problem(file, line_number, kind);
+                }
+                continue;
+            }
+
+            let mut rest = line;
+            let mut opcode: Option<(&str, String)> = None;
+            while !rest.is_empty() {
+                if let Some(header) = rest.strip_prefix('<') {
+                    let end = match header.find('>') {
+                        Some(end) => end,
+                        None => {
+                            problems.push(SfzProblemKind::UnterminatedHeader);
+                            break;
+                        }
+                    };
+                    let name = &header[..end];
+                    if name == "region" {
+                        self.sfz.regions += 1;
+                    } else if !HEADERS.contains(&name) {
+                        problems.push(SfzProblemKind::UnknownHeader(name.to_owned()));
+                    }
+                    rest = header[end + 1..].trim_start();
+                    continue;
+                }
+
+                let word_end = rest
+                    .find(|c: char| c.is_whitespace() || c == '<')
+                    .unwrap_or(rest.len());
+                let word = &rest[..word_end];
+                rest = rest[word_end..].trim_start();
+
+                match word.split_once('=') {
+                    Some((key, value)) => {
+                        if let Some((key, value)) = opcode.take() {
+                            problems.extend(self.opcode(key, &value));
+                        }
+                        opcode = Some((key, value.to_owned()));
+                    }
+                    // Values such as sample paths can contain spaces
+                    None => match &mut opcode {
+                        Some((_, value)) => {
+                            value.push(' ');
+                            value.push_str(word);
+                        }
+                        None => problems.push(SfzProblemKind::UnexpectedText(word.to_owned())),
+                    },
+                }
+            }
+            if let Some((key, value)) = opcode.take() {
+                problems.extend(self.opcode(key, &value));
+            }
+            for kind in problems {
+                self.problem(file, line_number, kind);
+            }
+        }
+    }
+
+    fn problem(&mut self, file: &Path, line: usize, kind: SfzProblemKind) {
+        self.sfz.problems.push(SfzProblem {
+            file: file.to_path_buf(),
+            line,
+            kind,
+        });
+    }
+
+    fn directive(&mut self, directive: &str, depth: usize) -> Option<SfzProblemKind> {
+        if let Some(include) = directive.strip_prefix("include") {
+            let include = self.expand(include.trim().trim_matches('"'));
+            let path = self.resolve(&include);
+            if depth >= MAX_INCLUDE_DEPTH {
+                return Some(SfzProblemKind::IncludeTooDeep);
+            }
+            match fs::read_to_string(&path) {
+                Ok(text) => self.check(&path, &text, depth + 1),
+                Err(_) => return Some(SfzProblemKind::MissingInclude(path)),
+            }
+        } else if let Some(define) = directive.strip_prefix("define") {
+            let mut parts = define.trim().splitn(2, char::is_whitespace);
+            if let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+                self.defines
+                    .insert(name.to_owned(), value.trim().to_owned());
+            }
+        } else {
+            return Some(SfzProblemKind::UnexpectedText(format!("#{}", directive)));
+        }
+        None
+    }
+
+    fn opcode(&mut self, key: &str, value: &str) -> Option<SfzProblemKind> {
+        match key {
+            "default_path" => self.default_path = self.expand(value),
+            "sample" if !value.starts_with('*') => {
+                let sample = format!("{}{}", self.default_path, self.expand(value));
+                let path = self.resolve(&sample);
+                self.sfz.samples.insert(path.clone());
+                if !path.is_file() {
+                    return Some(SfzProblemKind::MissingSample(path));
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Replaces `$NAME`s set with `#define`. Where several names match,
+    /// as `$VEL` and `$VELO` do, the longest wins.
+    fn expand(&self, text: &str) -> String {
+        let mut expanded = String::new();
+        let mut rest = text;
+        while let Some(start) = rest.find('$') {
+            expanded.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let name = self
+                .defines
+                .keys()
+                .filter(|name| rest.starts_with(name.as_str()))
+                .max_by_key(|name| name.len());
+            match name {
+                Some(name) => {
+                    expanded.push_str(&self.defines[name]);
+                    rest = &rest[name.len()..];
+                }
+                None => {
+                    expanded.push('$');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        expanded.push_str(rest);
+        expanded
+    }
+
+    /// Resolves a path from the file against its directory. SFZ files
+    /// usually use `\` as the separator, which is only one on Windows.
+    fn resolve(&self, path: &str) -> PathBuf {
+        self.dir.join(path.replace('\\', "/"))
+    }
+}
@@ -0,0 +1,199 @@
+/// Controllers that aren't stored as plain values: data entry, (N)RPN
+/// selection and channel mode messages
+fn is_stateless_controller(controller: u8) -> bool {
+    matches!(controller, 6 | 38 | 96..=101 | 120..=127)
+}
+
+/// Controllers that Reset All Controllers (CC 121) leaves alone, as
+/// recommended by the MIDI RP-015 practice
+fn survives_controller_reset(controller: u8) -> bool {
+    matches!(controller, 0 | 7 | 10 | 32 | 91 | 93)
+}
+
+/// The state of one MIDI channel, as set by the messages sent to it.
+///
+/// Values that were never sent are `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelState {
+    /// The last value of each controller. Data entry, (N)RPN selection and
+    /// channel mode controllers are never set here.
+    pub controllers: [Option<u8>; 128],
+    /// The last program change
+    pub program: Option<u8>,
+    /// The 14-bit pitch bend value
+    pub pitch_bend: Option<u16>,
+    /// The last channel pressure (aftertouch) value
+    pub channel_pressure: Option<u8>,
+    /// Pitch bend range (RPN 0) as semitones and cents
+    pub pitch_bend_range: Option<(u8, u8)>,
+    /// The selected RPN as (MSB, LSB), or `None` if an NRPN or nothing is
+    /// selected
+    rpn: Option<(Option<u8>, Option<u8>)>,
+}
+
+impl Default for ChannelState {
+    fn default() -> Self {
+        ChannelState {
+            controllers: [None; 128],
+            program: None,
+            pitch_bend: None,
+            channel_pressure: None,
+            pitch_bend_range: None,
+            rpn: None,
+        }
+    }
+}
+
+impl ChannelState {
+    /// Returns the bank select (MSB, LSB), CC 0 and 32
+    pub fn bank(&self) -> (Option<u8>, Option<u8>) {
+        (self.controllers[0], self.controllers[32])
+    }
+
+    /// Returns the channel volume, CC 7
+    pub fn volume(&self) -> Option<u8> {
+        self.controllers[7]
+    }
+
+    /// Returns the pan, CC 10
+    pub fn pan(&self) -> Option<u8> {
+        self.controllers[10]
+    }
+
+    fn control_change(&mut self, controller: u8, value: u8) {
+        match controller {
+            // RPN LSB/MSB
+            100 | 101 => {
+                let (mut msb, mut lsb) = self.rpn.unwrap_or((None, None));
+                if controller == 101 {
+                    msb = Some(value);
+                } else {
+                    lsb = Some(value);
+                }
+                // RPN 127/127 is the null function, i.e. nothing selected
+                self.rpn = match (msb, lsb) {
+                    (Some(127), Some(127)) => None,
+                    selected => Some(selected),
+                };
+            }
+            // NRPN LSB/MSB
+            98 | 99 => self.rpn = None,
+            // Data entry MSB/LSB
+            6 | 38 => {
+                if self.rpn == Some((Some(0), Some(0))) {
+                    let (semitones, cents) = self.pitch_bend_range.unwrap_or((2, 0));
+                    self.pitch_bend_range = Some(if controller == 6 {
+                        (value, cents)
+                    } else {
+                        (semitones, value)
+                    });
+                }
+            }
+            // Reset All Controllers
+            121 => {
+                for (controller, value) in self.controllers.iter_mut().enumerate() {
+                    if !survives_controller_reset(controller as u8) {
+                        *value = None;
+                    }
+                }
+                self.pitch_bend = None;
+                self.channel_pressure = None;
+                self.rpn = None;
+            }
+            _ if is_stateless_controller(controller) => {}
+            _ => self.controllers[controller as usize] = Some(value),
+        }
+    }
+
+    /// Returns the messages that bring a reset channel to this state, in
+    /// the order they should be sent
+    pub fn restore_messages(&self, channel: u8) -> Vec<u32> {
+        let status = |kind: u32| kind | (channel & 0x0F) as u32;
+        let cc = |controller: u32, value: u8| status(0xB0) | controller << 8 | (value as u32) << 16;
+
+        let mut messages = Vec::new();
+        // Bank select has to come before the program change
+        for controller in [0, 32] {
+            if let Some(value) = self.controllers[controller as usize] {
+                messages.push(cc(controller, value));
+            }
+        }
+        if let Some(program) = self.program {
+            messages.push(status(0xC0) | (program as u32) << 8);
+        }
+        if let Some((semitones, cents)) = self.pitch_bend_range {
+            messages.extend([
+                cc(101, 0),
+                cc(100, 0),
+                cc(6, semitones),
+                cc(38, cents),
+                // Deselect the RPN again so later data entry goes nowhere
+                cc(101, 127),
+                cc(100, 127),
+            ]);
+        }
+        for (controller, value) in self.controllers.iter().enumerate() {
+            if let (false, Some(value)) = (controller == 0 || controller == 32, value) {
+                messages.push(cc(controller as u32, *value));
+            }
+        }
+        if let Some(value) = self.pitch_bend {
+            messages.push(status(0xE0) | (value as u32 & 0x7F) << 8 | (value as u32 >> 7) << 16);
+        }
+        if let Some(pressure) = self.channel_pressure {
+            messages.push(status(0xD0) | (pressure as u32) << 8);
+        }
+        messages
+    }
+}
+
+/// The state of all 16 channels, updated from every sent message.
+///
+/// Clone it (or use [`KDMAPIStream::snapshot`](crate::KDMAPIStream::snapshot))
+/// to keep a snapshot, and send its [`restore_messages`](Self::restore_messages)
+/// after a reset to bring the synth back to that state.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MidiState {
+    channels: [ChannelState; 16],
+}
+
+impl MidiState {
+    /// Creates a state where nothing has been set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Updates the state with a sent short message, packed like
+    /// [`ShortMessage::pack`](crate::ShortMessage::pack)
+    pub fn update(&mut self, data: u32) {
+        let channel = &mut self.channels[(data & 0x0F) as usize];
+        let data1 = (data >> 8 & 0x7F) as u8;
+        let data2 = (data >> 16 & 0x7F) as u8;
+        match data & 0xF0 {
+            0xB0 => channel.control_change(data1, data2),
+            0xC0 => channel.program = Some(data1),
+            0xD0 => channel.channel_pressure = Some(data1),
+            0xE0 => channel.pitch_bend = Some(data1 as u16 | (data2 as u16) << 7),
+            _ => {}
+        }
+    }
+
+    /// Returns the state of `channel` (0-15)
+    pub fn channel(&self, channel: u8) -> &ChannelState {
+        &self.channels[channel as usize & 0x0F]
+    }
+
+    /// Returns the messages that bring a reset synth to this state
+    pub fn restore_messages(&self) -> Vec<u32> {
+        self.channels
+            .iter()
+            .enumerate()
+            .flat_map(|(channel, state)| state.restore_messages(channel as u8))
+            .collect()
+    }
+
+    /// Forgets everything, e.g. after the synth was reset
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
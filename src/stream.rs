@@ -0,0 +1,328 @@
+#[cfg(not(target_os = "windows"))]
+use std::ffi::CString;
+#[cfg(target_os = "windows")]
+use std::os::windows::ffi::OsStrExt;
+
+use std::{
+    cell::{RefCell, RefMut},
+    path::Path,
+};
+
+use crate::{
+    soundfont::SoundFontList, sysex, tracker::all_off_messages, KDMAPIBinds, KdmapiError,
+    MidiState, NoteTracker, ShortMessage,
+};
+
+/// Struct that provides access to KDMAPI's stream functions
+///
+/// Automatically calls `TerminateKDMAPIStream` when dropped (for shared
+/// streams, when the last one is dropped).
+///
+/// With note tracking enabled, sounding notes are also cut with
+/// [`panic`](Self::panic) before the stream is terminated.
+///
+/// A stream is `Send`, so it can be moved to (and dropped on) another
+/// thread, but not `Sync`: OmniMIDI doesn't document its functions as safe
+/// to call concurrently. To send from several threads, wrap it in a
+/// [`SharedStream`](crate::SharedStream), which serializes the calls, or
+/// queue events to a [`SenderThread`](crate::SenderThread) that owns it.
+pub struct KDMAPIStream {
+    binds: KDMAPIBinds,
+    shared: bool,
+    // `RefCell`s, which also keep the stream from being `Sync`
+    notes: Option<RefCell<NoteTracker>>,
+    state: Option<RefCell<MidiState>>,
+}
+
+impl KDMAPIStream {
+    pub(crate) fn new(binds: KDMAPIBinds, shared: bool) -> Self {
+        KDMAPIStream {
+            binds,
+            shared,
+            notes: None,
+            state: None,
+        }
+    }
+
+    /// Returns the bindings this stream was opened from
+    pub fn binds(&self) -> &KDMAPIBinds {
+        &self.binds
+    }
+
+    /// Returns whether this stream was opened with
+    /// [`KDMAPIBinds::open_shared_stream`]
+    pub fn is_shared(&self) -> bool {
+        self.shared
+    }
+
+    /// Enables or disables tracking of the notes sent through this stream.
+    /// Disabled by default.
+    pub fn set_note_tracking(&mut self, enabled: bool) {
+        match (enabled, &self.notes) {
+            (true, None) => self.notes = Some(RefCell::new(NoteTracker::new())),
+            (false, Some(_)) => self.notes = None,
+            _ => {}
+        }
+    }
+
+    /// Returns the notes currently sounding, or `None` if note tracking is
+    /// disabled
+    pub fn active_notes(&self) -> Option<Vec<(u8, u8)>> {
+        self.notes_mut().map(|notes| notes.active_notes())
+    }
+
+    fn notes_mut(&self) -> Option<RefMut<'_, NoteTracker>> {
+        self.notes.as_ref().map(RefCell::borrow_mut)
+    }
+
+    /// Enables or disables tracking of the [`MidiState`] set through this
+    /// stream. Disabled by default.
+    pub fn set_state_tracking(&mut self, enabled: bool) {
+        match (enabled, &self.state) {
+            (true, None) => self.state = Some(RefCell::new(MidiState::new())),
+            (false, Some(_)) => self.state = None,
+            _ => {}
+        }
+    }
+
+    /// Returns a copy of the tracked channel state, or `None` if state
+    /// tracking is disabled
+    pub fn snapshot(&self) -> Option<MidiState> {
+        self.state_mut().map(|state| state.clone())
+    }
+
+    /// Sends the messages that bring the synth to `snapshot`, e.g. after a
+    /// [`reset`](Self::reset) or on a newly opened stream.
+    ///
+    /// Keeps going if a send fails, and returns the first error.
+    pub fn restore(&self, snapshot: &MidiState) -> Result<(), KdmapiError> {
+        let mut result = Ok(());
+        for data in snapshot.restore_messages() {
+            let sent = self.send_direct_data(data);
+            if result.is_ok() {
+                result = sent;
+            }
+        }
+        result
+    }
+
+    fn state_mut(&self) -> Option<RefMut<'_, MidiState>> {
+        self.state.as_ref().map(RefCell::borrow_mut)
+    }
+
+    fn track(&self, data: u32) {
+        self.track_all(&[data]);
+    }
+
+    fn track_all(&self, data: &[u32]) {
+        if let Some(mut notes) = self.notes_mut() {
+            data.iter().for_each(|&data| notes.update(data));
+        }
+        if let Some(mut state) = self.state_mut() {
+            data.iter().for_each(|&data| state.update(data));
+        }
+    }
+
+    /// Calls `ResetKDMAPIStream`
+    ///
+    /// Clears the tracked notes and state, so take a
+    /// [`snapshot`](Self::snapshot) first to restore it afterwards.
+    pub fn reset(&self) {
+        unsafe {
+            (self.binds.vtable.reset_kdmapi_stream)();
+        }
+        if let Some(mut notes) = self.notes_mut() {
+            notes.clear();
+        }
+        if let Some(mut state) = self.state_mut() {
+            state.clear();
+        }
+    }
+
+    /// Stops every sound: sends a note off for each tracked note (if note
+    /// tracking is enabled), then sustain off, All Sound Off and All Notes
+    /// Off on every channel.
+    ///
+    /// Keeps going if a send fails, and returns the first error.
+    pub fn panic(&self) -> Result<(), KdmapiError> {
+        let note_offs = match self.notes_mut() {
+            Some(mut notes) => {
+                let note_offs = notes.note_offs();
+                notes.clear();
+                note_offs
+            }
+            None => Vec::new(),
+        };
+
+        let mut result = Ok(());
+        for data in note_offs.into_iter().chain(all_off_messages()) {
+            let sent = check_send(unsafe { (self.binds.vtable.send_direct_data_no_buf)(data) });
+            if result.is_ok() {
+                result = sent;
+            }
+        }
+        result
+    }
+
+    /// Calls `SendDirectData`
+    ///
+    /// Errors with [`KdmapiError::SendFailed`] if it returns a non-zero code.
+    pub fn send_direct_data(&self, data: u32) -> Result<(), KdmapiError> {
+        check_send(unsafe { (self.binds.vtable.send_direct_data)(data) })?;
+        self.track(data);
+        Ok(())
+    }
+
+    /// Calls `SendDirectDataNoBuf`
+    ///
+    /// Errors with [`KdmapiError::SendFailed`] if it returns a non-zero code.
+    pub fn send_direct_data_no_buf(&self, data: u32) -> Result<(), KdmapiError> {
+        check_send(unsafe { (self.binds.vtable.send_direct_data_no_buf)(data) })?;
+        self.track(data);
+        Ok(())
+    }
+
+    /// Calls `SendDirectData` for every packed message in `data`.
+    ///
+    /// KDMAPI has no export that takes several short messages at once
+    /// (`SendDirectLongData` only carries SysEx), so this is still one FFI
+    /// call per message. It's cheaper than calling
+    /// [`send_direct_data`](Self::send_direct_data) in a loop only because
+    /// note and state tracking is updated once for the whole batch.
+    ///
+    /// Stops at the first message that fails and returns its error; the
+    /// messages before it have been sent.
+    pub fn send_batch(&self, data: &[u32]) -> Result<(), KdmapiError> {
+        self.send_batch_with(self.binds.vtable.send_direct_data, data)
+    }
+
+    /// Calls `SendDirectDataNoBuf` for every packed message in `data`, like
+    /// [`send_batch`](Self::send_batch)
+    pub fn send_batch_no_buf(&self, data: &[u32]) -> Result<(), KdmapiError> {
+        self.send_batch_with(self.binds.vtable.send_direct_data_no_buf, data)
+    }
+
+    fn send_batch_with(
+        &self,
+        send: unsafe extern "C" fn(u32) -> u32,
+        data: &[u32],
+    ) -> Result<(), KdmapiError> {
+        let mut result = Ok(());
+        let mut sent = data.len();
+        for (index, &message) in data.iter().enumerate() {
+            if let Err(err) = check_send(unsafe { send(message) }) {
+                result = Err(err);
+                sent = index;
+                break;
+            }
+        }
+        self.track_all(&data[..sent]);
+        result
+    }
+
+    /// Packs `message` and sends it with `SendDirectData`
+    pub fn send(&self, message: ShortMessage) -> Result<(), KdmapiError> {
+        self.send_direct_data(message.pack())
+    }
+
+    /// Packs `message` and sends it with `SendDirectDataNoBuf`
+    pub fn send_no_buf(&self, message: ShortMessage) -> Result<(), KdmapiError> {
+        self.send_direct_data_no_buf(message.pack())
+    }
+
+    /// Sends a SysEx message with `SendDirectLongData`.
+    ///
+    /// `data` must be a complete message, starting with `F0` and ending with
+    /// `F7`. The header buffer is prepared before sending and unprepared
+    /// again before this returns.
+    pub fn send_sysex(&self, data: &[u8]) -> Result<(), KdmapiError> {
+        sysex::send_long_data(
+            &self.binds,
+            self.binds.vtable.send_direct_long_data,
+            "SendDirectLongData",
+            data,
+        )
+    }
+
+    /// Sends a SysEx message with `SendDirectLongDataNoBuf`.
+    ///
+    /// Same requirements as [`send_sysex`](Self::send_sysex).
+    pub fn send_sysex_no_buf(&self, data: &[u8]) -> Result<(), KdmapiError> {
+        sysex::send_long_data(
+            &self.binds,
+            self.binds.vtable.send_direct_long_data_no_buf,
+            "SendDirectLongDataNoBuf",
+            data,
+        )
+    }
+
+    /// Calls `LoadCustomSoundFontsList` to replace the loaded soundfonts
+    /// with the ones in an OmniMIDI soundfont list file.
+    ///
+    /// The path is passed as UTF-16 on Windows and as UTF-8 elsewhere.
+    /// Errors with [`KdmapiError::Unsupported`] if the loaded library doesn't
+    /// export the function.
+    pub fn load_soundfont_list(&self, path: &Path) -> Result<(), KdmapiError> {
+        let load = self
+            .binds
+            .vtable
+            .load_custom_soundfonts_list
+            .ok_or(KdmapiError::Unsupported("LoadCustomSoundFontsList"))?;
+        let invalid = || KdmapiError::InvalidPath(path.to_path_buf());
+
+        #[cfg(target_os = "windows")]
+        let loaded = {
+            let mut wide: Vec<u16> = path.as_os_str().encode_wide().collect();
+            if wide.contains(&0) {
+                return Err(invalid());
+            }
+            wide.push(0);
+            unsafe { load(wide.as_ptr()) }
+        };
+        #[cfg(not(target_os = "windows"))]
+        let loaded = {
+            let utf8 = path.to_str().ok_or_else(invalid)?;
+            let c_path = CString::new(utf8).map_err(|_| invalid())?;
+            unsafe { load(c_path.as_ptr()) }
+        };
+
+        if loaded {
+            Ok(())
+        } else {
+            Err(KdmapiError::SoundFontListRejected(path.to_path_buf()))
+        }
+    }
+
+    /// Writes `list` to a temporary file, loads it with
+    /// [`load_soundfont_list`](Self::load_soundfont_list) and removes the
+    /// file again
+    pub fn load_soundfonts(&self, list: &SoundFontList) -> Result<(), KdmapiError> {
+        let path = list.save_temp()?;
+        let result = self.load_soundfont_list(&path);
+        let _ = std::fs::remove_file(&path);
+        result
+    }
+
+    #[cfg(target_os = "windows")]
+    #[deprecated(note = "use `load_soundfont_list`, which reports why loading failed")]
+    pub fn load_custom_soundfonts_list(&self, path: &str) -> bool {
+        self.load_soundfont_list(Path::new(path)).is_ok()
+    }
+}
+
+pub(crate) fn check_send(code: u32) -> Result<(), KdmapiError> {
+    if code == 0 {
+        Ok(())
+    } else {
+        Err(KdmapiError::SendFailed(code))
+    }
+}
+
+impl Drop for KDMAPIStream {
+    fn drop(&mut self) {
+        if self.notes.is_some() {
+            let _ = self.panic();
+        }
+        self.binds.close_stream(self.shared);
+    }
+}
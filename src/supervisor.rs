@@ -0,0 +1,186 @@
+use std::{thread, time::Duration};
+
+use crate::{KDMAPIBinds, KDMAPIStream, KdmapiError, MidiSink, MidiState};
+
+/// How a [`SupervisedStream`] retries opening the stream
+#[derive(Debug, Clone)]
+pub struct RecoveryOptions {
+    /// Attempts to open the stream before giving up, at least 1
+    pub max_attempts: u32,
+    /// Wait after the first failed attempt, doubled after each further one
+    pub initial_backoff: Duration,
+    /// Upper limit of the wait between attempts
+    pub max_backoff: Duration,
+}
+
+impl Default for RecoveryOptions {
+    fn default() -> Self {
+        RecoveryOptions {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+/// An exclusive stream that reopens itself when it fails.
+///
+/// A failed send (a non-zero return from the `SendDirect*` functions) or a
+/// failed [`check_health`](Self::check_health) terminates the stream and
+/// initializes it again, backing off between attempts as set in
+/// [`RecoveryOptions`]. The channel state sent so far is then replayed, and
+/// the failed message is sent once more.
+///
+/// If every attempt fails, the error is returned and the stream stays
+/// closed until the next send or health check tries again.
+pub struct SupervisedStream {
+    binds: KDMAPIBinds,
+    options: RecoveryOptions,
+    stream: Option<KDMAPIStream>,
+    state: MidiState,
+    recoveries: u32,
+}
+
+impl SupervisedStream {
+    /// Opens an exclusive stream from `binds`, retrying as set in `options`
+    pub fn open(binds: &KDMAPIBinds, options: RecoveryOptions) -> Result<Self, KdmapiError> {
+        let mut stream = SupervisedStream {
+            binds: binds.clone(),
+            options,
+            stream: None,
+            state: MidiState::new(),
+            recoveries: 0,
+        };
+        stream.stream = Some(stream.open_with_backoff()?);
+        Ok(stream)
+    }
+
+    /// Returns the underlying stream, or `None` if reopening it failed
+    pub fn stream(&self) -> Option<&KDMAPIStream> {
+        self.stream.as_ref()
+    }
+
+    /// Returns whether the underlying stream is open
+    pub fn is_open(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Returns the channel state that is replayed after reopening
+    pub fn state(&self) -> &MidiState {
+        &self.state
+    }
+
+    /// Returns how many times the stream was reopened successfully
+    pub fn recoveries(&self) -> u32 {
+        self.recoveries
+    }
+
+    /// Reopens the stream if it is closed or `IsKDMAPIAvailable` returns
+    /// false
+    pub fn check_health(&mut self) -> Result<(), KdmapiError> {
+        if self.stream.is_some() && self.binds.is_kdmapi_available() {
+            return Ok(());
+        }
+        self.recover()
+    }
+
+    /// Terminates the stream, opens it again and replays the channel state
+    pub fn recover(&mut self) -> Result<(), KdmapiError> {
+        self.stream = None;
+        let stream = self.open_with_backoff()?;
+        self.stream = Some(stream);
+        self.recoveries += 1;
+        Ok(())
+    }
+
+    fn open_with_backoff(&self) -> Result<KDMAPIStream, KdmapiError> {
+        let mut backoff = self.options.initial_backoff;
+        let mut attempt = 1;
+        loop {
+            let err = match self.binds.open_stream() {
+                Ok(stream) => match stream.restore(&self.state) {
+                    Ok(()) => return Ok(stream),
+                    Err(err) => err,
+                },
+                // Retrying can't help if someone else holds the stream
+                Err(KdmapiError::StreamAlreadyOpen) => return Err(KdmapiError::StreamAlreadyOpen),
+                Err(err) => err,
+            };
+            if attempt >= self.options.max_attempts {
+                return Err(err);
+            }
+            attempt += 1;
+            thread::sleep(backoff);
+            backoff = (backoff * 2).min(self.options.max_backoff);
+        }
+    }
+
+    fn with_recovery(
+        &mut self,
+        send: impl Fn(&KDMAPIStream) -> Result<(), KdmapiError>,
+    ) -> Result<(), KdmapiError> {
+        if self.stream.is_none() {
+            self.recover()?;
+        }
+        match send(self.stream.as_ref().unwrap()) {
+            Err(KdmapiError::SendFailed(_)) => {
+                self.recover()?;
+                send(self.stream.as_ref().unwrap())
+            }
+            result => result,
+        }
+    }
+
+    /// Calls `SendDirectData`, reopening the stream if it fails
+    pub fn send_direct_data(&mut self, data: u32) -> Result<(), KdmapiError> {
+        self.with_recovery(|stream| stream.send_direct_data(data))?;
+        self.state.update(data);
+        Ok(())
+    }
+
+    /// Calls `SendDirectDataNoBuf`, reopening the stream if it fails
+    pub fn send_direct_data_no_buf(&mut self, data: u32) -> Result<(), KdmapiError> {
+        self.with_recovery(|stream| stream.send_direct_data_no_buf(data))?;
+        self.state.update(data);
+        Ok(())
+    }
+
+    /// Sends a SysEx message with `SendDirectLongData`, reopening the stream
+    /// if it fails
+    pub fn send_sysex(&mut self, data: &[u8]) -> Result<(), KdmapiError> {
+        self.with_recovery(|stream| stream.send_sysex(data))
+    }
+
+    /// Sends a SysEx message with `SendDirectLongDataNoBuf`, reopening the
+    /// stream if it fails
+    pub fn send_sysex_no_buf(&mut self, data: &[u8]) -> Result<(), KdmapiError> {
+        self.with_recovery(|stream| stream.send_sysex_no_buf(data))
+    }
+
+    /// Calls `ResetKDMAPIStream` and forgets the channel state
+    pub fn reset(&mut self) {
+        if let Some(stream) = &self.stream {
+            stream.reset();
+        }
+        self.state.clear();
+    }
+}
+
+impl MidiSink for SupervisedStream {
+    fn send_short(&mut self, data: u32) -> Result<(), KdmapiError> {
+        self.send_direct_data(data)
+    }
+
+    fn send_short_no_buf(&mut self, data: u32) -> Result<(), KdmapiError> {
+        self.send_direct_data_no_buf(data)
+    }
+
+    fn send_sysex(&mut self, data: &[u8]) -> Result<(), KdmapiError> {
+        SupervisedStream::send_sysex(self, data)
+    }
+
+    fn reset(&mut self) -> Result<(), KdmapiError> {
+        SupervisedStream::reset(self);
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+use std::{mem, ptr, thread};
+
+use crate::{stream::check_send, KDMAPIBinds, KdmapiError};
+
+/// Signature shared by `PrepareLongData`, `UnprepareLongData`,
+/// `SendDirectLongData` and `SendDirectLongDataNoBuf`
+pub type LongDataFn = unsafe extern "C" fn(*mut MidiHdr, u32) -> u32;
+
+/// `MIDIERR_STILLPLAYING`, returned by `UnprepareLongData` while the buffer
+/// is still in use
+const MIDIERR_STILLPLAYING: u32 = 65;
+
+/// How many times to retry `UnprepareLongData` before giving up
+const UNPREPARE_RETRIES: usize = 1000;
+
+/// Layout-compatible with the Windows `MIDIHDR` struct that KDMAPI expects
+#[repr(C)]
+#[derive(Debug)]
+pub struct MidiHdr {
+    /// `lpData`, the message bytes
+    pub data: *mut u8,
+    /// `dwBufferLength`
+    pub buffer_length: u32,
+    /// `dwBytesRecorded`, the number of valid bytes in `data`
+    pub bytes_recorded: u32,
+    /// `dwUser`
+    pub user: usize,
+    /// `dwFlags`
+    pub flags: u32,
+    /// `lpNext`
+    pub next: *mut MidiHdr,
+    /// `reserved`
+    pub reserved: usize,
+    /// `dwOffset`
+    pub offset: u32,
+    /// `dwReserved`
+    pub reserved_array: [usize; 8],
+}
+
+/// Checks that `data` is a single SysEx message: `F0`, any number of data
+/// bytes below `0x80`, then `F7`.
+pub fn validate_sysex(data: &[u8]) -> Result<(), KdmapiError> {
+    match data {
+        [0xF0, body @ .., 0xF7] if body.iter().all(|&b| b < 0x80) => Ok(()),
+        _ => Err(KdmapiError::InvalidSysEx),
+    }
+}
+
+pub(crate) fn send_long_data(
+    binds: &KDMAPIBinds,
+    send: Option<LongDataFn>,
+    send_name: &'static str,
+    data: &[u8],
+) -> Result<(), KdmapiError> {
+    validate_sysex(data)?;
+
+    let prepare = binds
+        .vtable
+        .prepare_long_data
+        .ok_or(KdmapiError::Unsupported("PrepareLongData"))?;
+    let unprepare = binds
+        .vtable
+        .unprepare_long_data
+        .ok_or(KdmapiError::Unsupported("UnprepareLongData"))?;
+    let send = send.ok_or(KdmapiError::Unsupported(send_name))?;
+
+    // KDMAPI takes a mutable buffer, so don't hand it the caller's slice
+    let mut buffer = data.to_vec();
+    let mut header = Box::new(MidiHdr {
+        data: buffer.as_mut_ptr(),
+        buffer_length: buffer.len() as u32,
+        bytes_recorded: buffer.len() as u32,
+        user: 0,
+        flags: 0,
+        next: ptr::null_mut(),
+        reserved: 0,
+        offset: 0,
+        reserved_array: [0; 8],
+    });
+    let header_size = mem::size_of::<MidiHdr>() as u32;
+
+    unsafe {
+        check_send(prepare(&mut *header, header_size))?;
+        let result = send(&mut *header, header_size);
+
+        let mut retries = 0;
+        while unprepare(&mut *header, header_size) == MIDIERR_STILLPLAYING {
+            retries += 1;
+            if retries == UNPREPARE_RETRIES {
+                // KDMAPI still holds on to the buffer, so it must never be freed
+                mem::forget(buffer);
+                mem::forget(header);
+                break;
+            }
+            thread::yield_now();
+        }
+
+        check_send(result)
+    }
+}
@@ -0,0 +1,97 @@
+/// Counts the notes that are currently sounding on each channel, based on
+/// the short messages sent.
+///
+/// Repeated note ons for the same key are counted, so that every one of
+/// them gets a matching note off.
+#[derive(Clone)]
+pub struct NoteTracker {
+    counts: [[u16; 128]; 16],
+}
+
+impl Default for NoteTracker {
+    fn default() -> Self {
+        NoteTracker {
+            counts: [[0; 128]; 16],
+        }
+    }
+}
+
+impl NoteTracker {
+    /// Creates a tracker with no sounding notes
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Updates the tracker with a sent short message, packed like
+    /// [`ShortMessage::pack`](crate::ShortMessage::pack). Anything other than
+    /// note on/off is ignored.
+    pub fn update(&mut self, data: u32) {
+        let channel = (data & 0x0F) as usize;
+        let key = (data >> 8 & 0x7F) as usize;
+        let velocity = data >> 16 & 0x7F;
+        let count = &mut self.counts[channel][key];
+        match data & 0xF0 {
+            0x90 if velocity != 0 => *count = count.saturating_add(1),
+            0x80 | 0x90 => *count = count.saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    /// Returns whether `key` is sounding on `channel`
+    pub fn is_active(&self, channel: u8, key: u8) -> bool {
+        self.counts[channel as usize & 0x0F][key as usize & 0x7F] != 0
+    }
+
+    /// Returns every sounding `(channel, key)` pair
+    pub fn active_notes(&self) -> Vec<(u8, u8)> {
+        let mut notes = Vec::new();
+        for (channel, keys) in self.counts.iter().enumerate() {
+            for (key, count) in keys.iter().enumerate() {
+                if *count != 0 {
+                    notes.push((channel as u8, key as u8));
+                }
+            }
+        }
+        notes
+    }
+
+    /// Returns the number of sounding notes, counting repeated note ons
+    pub fn active_count(&self) -> usize {
+        self.counts
+            .iter()
+            .flat_map(|keys| keys.iter())
+            .map(|count| *count as usize)
+            .sum()
+    }
+
+    /// Returns a packed note off for every sounding note, one per note on
+    pub fn note_offs(&self) -> Vec<u32> {
+        let mut note_offs = Vec::new();
+        for (channel, keys) in self.counts.iter().enumerate() {
+            for (key, count) in keys.iter().enumerate() {
+                for _ in 0..*count {
+                    note_offs.push(0x80 | channel as u32 | (key as u32) << 8);
+                }
+            }
+        }
+        note_offs
+    }
+
+    /// Forgets every sounding note
+    pub fn clear(&mut self) {
+        self.counts = [[0; 128]; 16];
+    }
+}
+
+/// Packed sustain off (CC 64), All Sound Off (CC 120) and All Notes Off
+/// (CC 123) for every channel. Sustain goes first, or held notes would keep
+/// sounding on synths that honor it over All Notes Off.
+pub(crate) fn all_off_messages() -> impl Iterator<Item = u32> {
+    (0..16).flat_map(|channel| {
+        [
+            0xB0 | channel | 64 << 8,
+            0xB0 | channel | 120 << 8,
+            0xB0 | channel | 123 << 8,
+        ]
+    })
+}
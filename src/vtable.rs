@@ -0,0 +1,107 @@
+#[cfg(not(target_os = "windows"))]
+use std::os::raw::c_char;
+use std::os::raw::c_void;
+
+use libloading::Library;
+
+use crate::{DriverDebugInfo, KdmapiLoadError, LongDataFn};
+
+/// The table of KDMAPI functions used by [`KDMAPIBinds`](crate::KDMAPIBinds)
+///
+/// Usually resolved from the OmniMIDI library, but can be filled in by hand
+/// and passed to [`KDMAPIBinds::from_vtable`](crate::KDMAPIBinds::from_vtable).
+/// Optional exports are `None` when they aren't available.
+#[derive(Clone, Copy)]
+pub struct KdmapiVTable {
+    /// `IsKDMAPIAvailable`
+    pub is_kdmapi_available: unsafe extern "C" fn() -> bool,
+    /// `InitializeKDMAPIStream`
+    pub initialize_kdmapi_stream: unsafe extern "C" fn() -> i32,
+    /// `TerminateKDMAPIStream`
+    pub terminate_kdmapi_stream: unsafe extern "C" fn() -> i32,
+    /// `ResetKDMAPIStream`
+    pub reset_kdmapi_stream: unsafe extern "C" fn(),
+    /// `SendDirectData`
+    pub send_direct_data: unsafe extern "C" fn(u32) -> u32,
+    /// `SendDirectDataNoBuf`
+    pub send_direct_data_no_buf: unsafe extern "C" fn(u32) -> u32,
+    /// `LoadCustomSoundFontsList`, taking a null-terminated UTF-16 path
+    #[cfg(target_os = "windows")]
+    pub load_custom_soundfonts_list: Option<unsafe extern "C" fn(*const u16) -> bool>,
+    /// `LoadCustomSoundFontsList`, taking a null-terminated UTF-8 path
+    #[cfg(not(target_os = "windows"))]
+    pub load_custom_soundfonts_list: Option<unsafe extern "C" fn(*const c_char) -> bool>,
+    /// `PrepareLongData`
+    pub prepare_long_data: Option<LongDataFn>,
+    /// `UnprepareLongData`
+    pub unprepare_long_data: Option<LongDataFn>,
+    /// `SendDirectLongData`
+    pub send_direct_long_data: Option<LongDataFn>,
+    /// `SendDirectLongDataNoBuf`
+    pub send_direct_long_data_no_buf: Option<LongDataFn>,
+    /// `ReturnKDMAPIVer`, writing the major, minor, build and revision
+    pub return_kdmapi_ver:
+        Option<unsafe extern "C" fn(*mut u32, *mut u32, *mut u32, *mut u32) -> bool>,
+    /// `DriverSettings`, taking the setting, the mode, and a pointer to the
+    /// value with its size
+    pub driver_settings: Option<unsafe extern "C" fn(u32, u32, *mut c_void, u32) -> bool>,
+    /// `GetDriverDebugInfo`
+    pub get_driver_debug_info: Option<unsafe extern "C" fn() -> *const DriverDebugInfo>,
+}
+
+impl KdmapiVTable {
+    /// Resolves every export from `lib`, reporting all required ones that
+    /// are missing at once.
+    pub(crate) fn from_library(lib: &Library) -> Result<Self, KdmapiLoadError> {
+        let mut missing = Vec::new();
+        let is_kdmapi_available = get_symbol(lib, "IsKDMAPIAvailable", &mut missing);
+        let initialize_kdmapi_stream = get_symbol(lib, "InitializeKDMAPIStream", &mut missing);
+        let terminate_kdmapi_stream = get_symbol(lib, "TerminateKDMAPIStream", &mut missing);
+        let reset_kdmapi_stream = get_symbol(lib, "ResetKDMAPIStream", &mut missing);
+        let send_direct_data = get_symbol(lib, "SendDirectData", &mut missing);
+        let send_direct_data_no_buf = get_symbol(lib, "SendDirectDataNoBuf", &mut missing);
+
+        let vtable = (|| {
+            Some(KdmapiVTable {
+                is_kdmapi_available: is_kdmapi_available?,
+                initialize_kdmapi_stream: initialize_kdmapi_stream?,
+                terminate_kdmapi_stream: terminate_kdmapi_stream?,
+                reset_kdmapi_stream: reset_kdmapi_stream?,
+                send_direct_data: send_direct_data?,
+                send_direct_data_no_buf: send_direct_data_no_buf?,
+                load_custom_soundfonts_list: get_optional_symbol(lib, "LoadCustomSoundFontsList"),
+                prepare_long_data: get_optional_symbol(lib, "PrepareLongData"),
+                unprepare_long_data: get_optional_symbol(lib, "UnprepareLongData"),
+                send_direct_long_data: get_optional_symbol(lib, "SendDirectLongData"),
+                send_direct_long_data_no_buf: get_optional_symbol(lib, "SendDirectLongDataNoBuf"),
+                return_kdmapi_ver: get_optional_symbol(lib, "ReturnKDMAPIVer"),
+                driver_settings: get_optional_symbol(lib, "DriverSettings"),
+                get_driver_debug_info: get_optional_symbol(lib, "GetDriverDebugInfo"),
+            })
+        })();
+
+        vtable.ok_or(KdmapiLoadError::MissingSymbols(missing))
+    }
+}
+
+/// Looks up an export that older KDMAPI builds may not have
+fn get_optional_symbol<T: Copy>(lib: &Library, name: &str) -> Option<T> {
+    let mut symbol_name = Vec::with_capacity(name.len() + 1);
+    symbol_name.extend_from_slice(name.as_bytes());
+    symbol_name.push(0);
+
+    unsafe { lib.get::<T>(&symbol_name).ok().map(|symbol| *symbol) }
+}
+
+/// Looks up a single export, recording its name in `missing` if it isn't there.
+fn get_symbol<T: Copy>(
+    lib: &Library,
+    name: &'static str,
+    missing: &mut Vec<&'static str>,
+) -> Option<T> {
+    let symbol = get_optional_symbol(lib, name);
+    if symbol.is_none() {
+        missing.push(name);
+    }
+    symbol
+}
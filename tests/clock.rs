@@ -0,0 +1,110 @@
+use std::time::Duration;
+
+use kdmapi::{
+    smf::Smf, Clock, MidiPlayer, PlaybackState, RecordingSink, Scheduler, SinkEvent, VirtualClock,
+};
+
+fn ms(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+}
+
+#[test]
+fn scheduler_sends_as_the_clock_advances() {
+    let clock = VirtualClock::new();
+    let start = clock.now();
+    let scheduler = Scheduler::with_clock(RecordingSink::with_clock(clock.clone()), clock.clone());
+    scheduler.schedule(start + ms(10), 0x643C90);
+    scheduler.schedule(start + ms(20), 0x003C80);
+    scheduler.schedule(start + ms(20), 0x644090);
+
+    clock.advance(ms(9));
+    scheduler.wait_due();
+    assert_eq!(scheduler.pending(), 3);
+
+    clock.advance(ms(1));
+    scheduler.wait_due();
+    assert_eq!(scheduler.pending(), 2);
+
+    clock.advance(ms(100));
+    scheduler.wait_due();
+    assert_eq!(scheduler.pending(), 0);
+
+    let stats = scheduler.stats();
+    assert_eq!(stats.events_sent, 3);
+    assert_eq!(stats.late_events, 2);
+    assert_eq!(stats.max_lateness, ms(90));
+
+    let sink = scheduler.into_sink();
+    let recorded: Vec<_> = sink
+        .events()
+        .iter()
+        .map(|event| (event.time - start, event.event.clone()))
+        .collect();
+    assert_eq!(
+        recorded,
+        vec![
+            (ms(10), SinkEvent::Short(0x643C90)),
+            (ms(110), SinkEvent::Short(0x003C80)),
+            (ms(110), SinkEvent::Short(0x644090)),
+        ]
+    );
+}
+
+#[test]
+fn player_follows_the_clock() {
+    // 500 ticks per quarter at the default 120 BPM, so one tick is 1 ms
+    let mut file = b"MThd\0\0\0\x06\0\0\0\x01\x01\xF4".to_vec();
+    let track = [
+        0x00, 0x90, 0x3C, 0x64, 0x64, 0x80, 0x3C, 0x00, 0x81, 0x16, 0x90, 0x40, 0x64, 0x00, 0xFF,
+        0x2F, 0x00,
+    ];
+    file.extend(b"MTrk");
+    file.extend((track.len() as u32).to_be_bytes());
+    file.extend(track);
+    let smf = Smf::parse(&file).unwrap();
+
+    let clock = VirtualClock::new();
+    let start = clock.now();
+    let sink = RecordingSink::with_clock(clock.clone());
+    let player = MidiPlayer::with_clock(sink, &smf, clock.clone());
+    player.play();
+    player.wait_due();
+
+    clock.advance(ms(99));
+    player.wait_due();
+    assert_eq!(player.position(), ms(99));
+
+    clock.advance(ms(1));
+    player.wait_due();
+    clock.advance(ms(20));
+    player.pause();
+    player.wait_due();
+
+    // Time passes while paused without moving the song
+    clock.advance(ms(1000));
+    player.wait_due();
+    assert_eq!(player.position(), ms(120));
+
+    player.play();
+    clock.advance(ms(130));
+    player.wait_due();
+    assert_eq!(player.state(), PlaybackState::Finished);
+
+    let sink = player.into_sink();
+    let mut expected = vec![(ms(0), 0x643C90), (ms(100), 0x003C80)];
+    for channel in 0..16 {
+        expected.push((ms(120), 0x40B0 | channel));
+        expected.push((ms(120), 0x78B0 | channel));
+        expected.push((ms(120), 0x7BB0 | channel));
+    }
+    expected.push((ms(1250), 0x644090));
+    let recorded: Vec<_> = sink
+        .events()
+        .iter()
+        .map(|event| match event.event {
+            SinkEvent::Short(data) => (event.time - start, data),
+            ref other => panic!("unexpected {:?}", other),
+        })
+        .collect();
+    assert_eq!(recorded, expected);
+}
@@ -0,0 +1,85 @@
+use std::{env, path::PathBuf};
+
+use kdmapi::{KdmapiLoadError, KdmapiLoader, DEFAULT_ENV_VAR};
+
+/// Loads with `loader`, which must fail, and returns the paths it tried
+fn attempts(loader: &KdmapiLoader) -> Vec<PathBuf> {
+    match loader.load() {
+        Err(KdmapiLoadError::LibraryNotFound(attempts)) => {
+            attempts.into_iter().map(|attempt| attempt.path).collect()
+        }
+        Err(err) => panic!("unexpected error: {}", err),
+        Ok(_) => panic!("loaded a library that shouldn't exist"),
+    }
+}
+
+fn exe_dir() -> PathBuf {
+    env::current_exe().unwrap().parent().unwrap().to_path_buf()
+}
+
+#[test]
+fn tries_candidates_in_order() {
+    env::set_var("KDMAPI_LOADER_TEST_ORDER", "/missing/env/kdmapi.so");
+    let loader = KdmapiLoader::new()
+        .path("/missing/first/kdmapi.so")
+        .path("/missing/second/kdmapi.so")
+        .env_var("KDMAPI_LOADER_TEST_ORDER")
+        .exe_subdir("kdmapi-missing")
+        .search_dir("/missing/search")
+        .fallback_names(vec!["kdmapi-missing-a", "kdmapi-missing-b"]);
+
+    let exe_dir = exe_dir();
+    let expected = vec![
+        PathBuf::from("/missing/first/kdmapi.so"),
+        PathBuf::from("/missing/second/kdmapi.so"),
+        PathBuf::from("/missing/env/kdmapi.so"),
+        exe_dir.join("kdmapi-missing-a"),
+        exe_dir.join("kdmapi-missing-b"),
+        exe_dir.join("kdmapi-missing").join("kdmapi-missing-a"),
+        exe_dir.join("kdmapi-missing").join("kdmapi-missing-b"),
+        PathBuf::from("/missing/search/kdmapi-missing-a"),
+        PathBuf::from("/missing/search/kdmapi-missing-b"),
+        PathBuf::from("kdmapi-missing-a"),
+        PathBuf::from("kdmapi-missing-b"),
+    ];
+    assert_eq!(loader.candidates(), expected);
+    assert_eq!(attempts(&loader), expected);
+}
+
+#[test]
+fn honours_the_default_env_var() {
+    let loader = KdmapiLoader::new()
+        .search_exe_dir(false)
+        .system_search(false)
+        .fallback_names(vec!["kdmapi-missing"]);
+
+    assert_eq!(DEFAULT_ENV_VAR, "KDMAPI_LIBRARY");
+    env::set_var(DEFAULT_ENV_VAR, "/missing/env/kdmapi.so");
+    assert_eq!(attempts(&loader), [PathBuf::from("/missing/env/kdmapi.so")]);
+    assert!(attempts(&loader.clone().no_env_var()).is_empty());
+
+    // An empty value counts as unset
+    env::set_var(DEFAULT_ENV_VAR, "");
+    assert!(attempts(&loader).is_empty());
+    env::remove_var(DEFAULT_ENV_VAR);
+}
+
+#[test]
+fn exe_subdirs_are_searched_without_the_exe_dir() {
+    let loader = KdmapiLoader::new()
+        .no_env_var()
+        .search_exe_dir(false)
+        .system_search(false)
+        .exe_subdir("lib")
+        .exe_subdir("plugins/kdmapi")
+        .fallback_names(vec!["kdmapi-missing"]);
+
+    let exe_dir = exe_dir();
+    assert_eq!(
+        attempts(&loader),
+        [
+            exe_dir.join("lib").join("kdmapi-missing"),
+            exe_dir.join("plugins/kdmapi").join("kdmapi-missing"),
+        ]
+    );
+}
@@ -0,0 +1,88 @@
+use kdmapi::{Channel, MessageError, ShortMessage, U14, U7};
+
+#[test]
+fn every_message_round_trips() {
+    let messages = [
+        (ShortMessage::note_off(1, 60, 64).unwrap(), 0x403C81),
+        (ShortMessage::note_on(0, 60, 100).unwrap(), 0x643C90),
+        (ShortMessage::poly_pressure(2, 61, 10).unwrap(), 0x0A3DA2),
+        (ShortMessage::control_change(15, 7, 127).unwrap(), 0x7F07BF),
+        (ShortMessage::program_change(3, 42).unwrap(), 0x002AC3),
+        (ShortMessage::channel_pressure(4, 90).unwrap(), 0x005AD4),
+        (ShortMessage::pitch_bend(5, 0x3FFF).unwrap(), 0x7F7FE5),
+        (ShortMessage::pitch_bend(5, 0x2001).unwrap(), 0x4001E5),
+        (ShortMessage::TimingClock, 0xF8),
+        (ShortMessage::Start, 0xFA),
+        (ShortMessage::Continue, 0xFB),
+        (ShortMessage::Stop, 0xFC),
+        (ShortMessage::ActiveSensing, 0xFE),
+        (ShortMessage::SystemReset, 0xFF),
+    ];
+    for (message, packed) in messages {
+        assert_eq!(message.pack(), packed, "{:?}", message);
+        assert_eq!(u32::from(message), packed);
+        assert_eq!(ShortMessage::unpack(packed), Ok(message));
+    }
+}
+
+#[test]
+fn channel_messages_report_their_channel() {
+    let channel = Channel::new(9).unwrap();
+    assert_eq!(
+        ShortMessage::note_on(9, 36, 127).unwrap().channel(),
+        Some(channel)
+    );
+    assert_eq!(ShortMessage::SystemReset.channel(), None);
+}
+
+#[test]
+fn out_of_range_values_are_rejected() {
+    assert_eq!(Channel::new(15).map(Channel::get), Ok(15));
+    assert_eq!(Channel::new(16), Err(MessageError::InvalidChannel(16)));
+    assert_eq!(U7::new(127).map(U7::get), Ok(127));
+    assert_eq!(U7::new(128), Err(MessageError::InvalidU7(128)));
+    assert_eq!(U14::new(0x3FFF).map(U14::get), Ok(0x3FFF));
+    assert_eq!(U14::new(0x4000), Err(MessageError::InvalidU14(0x4000)));
+
+    assert_eq!(
+        ShortMessage::note_on(16, 60, 100),
+        Err(MessageError::InvalidChannel(16))
+    );
+    assert_eq!(
+        ShortMessage::control_change(0, 128, 0),
+        Err(MessageError::InvalidU7(128))
+    );
+    assert_eq!(
+        ShortMessage::pitch_bend(0, 0x4000),
+        Err(MessageError::InvalidU14(0x4000))
+    );
+}
+
+#[test]
+fn data_bytes_with_the_high_bit_are_rejected() {
+    assert_eq!(
+        ShortMessage::unpack(0x40BC90),
+        Err(MessageError::InvalidU7(0xBC))
+    );
+    assert_eq!(
+        ShortMessage::unpack(0x80_0000 | 0x40E0),
+        Err(MessageError::InvalidU7(0x80))
+    );
+}
+
+#[test]
+fn running_status_and_undefined_status_bytes_are_rejected() {
+    // A data byte where the status should be, as with running status
+    assert_eq!(
+        ShortMessage::unpack(0x643C),
+        Err(MessageError::InvalidStatus(0x3C))
+    );
+    assert_eq!(ShortMessage::unpack(0), Err(MessageError::InvalidStatus(0)));
+    // SysEx, system common and undefined system messages
+    for status in [0xF0, 0xF1, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF9, 0xFD] {
+        assert_eq!(
+            ShortMessage::unpack(status),
+            Err(MessageError::InvalidStatus(status as u8))
+        );
+    }
+}
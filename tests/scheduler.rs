@@ -0,0 +1,115 @@
+use std::time::{Duration, Instant};
+
+use kdmapi::{
+    KdmapiError, MidiEvent, MidiSink, RecordingSink, Scheduler, ShortMessage, VirtualClock,
+};
+
+#[test]
+fn sends_events_in_time_order() {
+    let scheduler = Scheduler::new(RecordingSink::new());
+    let start = Instant::now() + Duration::from_millis(20);
+    scheduler.schedule_all(vec![
+        (start + Duration::from_millis(30), 0x003C80),
+        (start, 0x643C90),
+        (start + Duration::from_millis(10), 0x644090),
+        // Same time as the first note on, so it goes after it
+        (start, 0x644390),
+    ]);
+    assert_eq!(scheduler.pending(), 4);
+    scheduler.wait_idle();
+
+    let stats = scheduler.stats();
+    assert_eq!(stats.events_sent, 4);
+    assert_eq!(scheduler.pending(), 0);
+
+    let sink = scheduler.into_sink();
+    assert_eq!(
+        sink.short_messages(),
+        vec![0x643C90, 0x644390, 0x644090, 0x003C80]
+    );
+    for (event, offset) in sink.events().iter().zip([0, 0, 10, 30]) {
+        assert!(event.time >= start + Duration::from_millis(offset));
+    }
+}
+
+#[test]
+fn sends_past_events_right_away_and_counts_them_late() {
+    let scheduler = Scheduler::new(RecordingSink::new());
+    scheduler.schedule(
+        Instant::now() - Duration::from_millis(50),
+        ShortMessage::note_on(0, 0x3C, 0x64).unwrap(),
+    );
+    scheduler.schedule_in(Duration::ZERO, MidiEvent::SysEx(vec![0xF0, 0x7E, 0xF7]));
+    scheduler.wait_idle();
+
+    let stats = scheduler.stats();
+    assert_eq!(stats.events_sent, 2);
+    assert!(stats.late_events >= 1);
+    assert!(stats.max_lateness >= Duration::from_millis(50));
+    assert!(stats.total_lateness >= stats.max_lateness);
+}
+
+#[test]
+fn counts_send_errors() {
+    let scheduler = Scheduler::new(RecordingSink::new());
+    // Not terminated with F7
+    scheduler.schedule_in(Duration::ZERO, MidiEvent::SysEx(vec![0xF0, 0x7E]));
+    scheduler.wait_idle();
+
+    let stats = scheduler.stats();
+    assert_eq!((stats.events_sent, stats.send_errors), (0, 1));
+}
+
+#[test]
+fn clear_discards_pending_events() {
+    let clock = VirtualClock::new();
+    let scheduler = Scheduler::with_clock(RecordingSink::with_clock(clock.clone()), clock.clone());
+    scheduler.schedule_in(Duration::from_secs(60), 0x643C90);
+    scheduler.schedule_in(Duration::from_millis(1), 0x644090);
+    clock.advance(Duration::from_millis(1));
+    scheduler.wait_due();
+    assert_eq!(scheduler.pending(), 1);
+    scheduler.clear();
+    scheduler.wait_idle();
+
+    assert_eq!(scheduler.into_sink().short_messages(), vec![0x644090]);
+}
+
+struct PanickingSink;
+
+impl MidiSink for PanickingSink {
+    fn send_short(&mut self, _data: u32) -> Result<(), KdmapiError> {
+        panic!("sink failed");
+    }
+
+    fn send_short_no_buf(&mut self, _data: u32) -> Result<(), KdmapiError> {
+        panic!("sink failed");
+    }
+
+    fn send_sysex(&mut self, _data: &[u8]) -> Result<(), KdmapiError> {
+        panic!("sink failed");
+    }
+
+    fn reset(&mut self) -> Result<(), KdmapiError> {
+        Ok(())
+    }
+}
+
+#[test]
+fn waiting_stops_when_the_sink_panics() {
+    let scheduler = Scheduler::new(PanickingSink);
+    scheduler.schedule_in(Duration::ZERO, 0x643C90);
+    scheduler.schedule_in(Duration::from_millis(1), 0x644090);
+    scheduler.wait_idle();
+    scheduler.wait_due();
+    assert_eq!(scheduler.pending(), 0);
+}
+
+#[test]
+#[should_panic(expected = "the dispatch thread panicked")]
+fn into_sink_reports_a_panicked_sink() {
+    let scheduler = Scheduler::new(PanickingSink);
+    scheduler.schedule_in(Duration::ZERO, 0x643C90);
+    scheduler.wait_idle();
+    scheduler.into_sink();
+}
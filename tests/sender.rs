@@ -0,0 +1,263 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use kdmapi::{
+    KdmapiError, MidiSink, OverflowPolicy, QueueError, RecordingSink, SenderOptions, SenderThread,
+};
+
+#[test]
+fn sends_from_many_threads_in_order() {
+    let thread = SenderThread::spawn(RecordingSink::new(), SenderOptions::default());
+    let producers: Vec<_> = (0..4u32)
+        .map(|producer| {
+            let sender = thread.sender();
+            thread::spawn(move || {
+                for i in 0..5000u32 {
+                    // Producer in the channel nibble, sequence in the data bytes
+                    sender.send(0x90 | producer | (i & 0x3FFF) << 8).unwrap();
+                }
+            })
+        })
+        .collect();
+    for producer in producers {
+        producer.join().unwrap();
+    }
+
+    let stats = thread.stats();
+    let sink = thread.into_sink();
+    let messages = sink.short_messages();
+    assert_eq!(messages.len(), 20000);
+    for producer in 0..4 {
+        let sequence: Vec<_> = messages
+            .iter()
+            .filter(|data| *data & 0x0F == producer)
+            .map(|data| data >> 8 & 0x3FFF)
+            .collect();
+        assert_eq!(sequence, (0..5000).map(|i| i & 0x3FFF).collect::<Vec<_>>());
+    }
+    assert_eq!(stats.events_dropped, 0);
+}
+
+/// A sink that holds up the sender thread until it's opened
+struct GatedSink {
+    inner: RecordingSink,
+    gate: Arc<(Mutex<(bool, bool)>, Condvar)>,
+}
+
+impl GatedSink {
+    fn new() -> (Self, Gate) {
+        let gate = Arc::new((Mutex::new((false, false)), Condvar::new()));
+        let sink = GatedSink {
+            inner: RecordingSink::new(),
+            gate: gate.clone(),
+        };
+        (sink, Gate(gate))
+    }
+
+    fn wait(&self) {
+        let (lock, cond) = &*self.gate;
+        let mut state = lock.lock().unwrap();
+        // (entered, open)
+        state.0 = true;
+        cond.notify_all();
+        while !state.1 {
+            state = cond.wait(state).unwrap();
+        }
+    }
+}
+
+impl MidiSink for GatedSink {
+    fn send_short(&mut self, data: u32) -> Result<(), KdmapiError> {
+        self.wait();
+        self.inner.send_short(data)
+    }
+
+    fn send_short_no_buf(&mut self, data: u32) -> Result<(), KdmapiError> {
+        self.wait();
+        self.inner.send_short_no_buf(data)
+    }
+
+    fn send_sysex(&mut self, data: &[u8]) -> Result<(), KdmapiError> {
+        self.wait();
+        self.inner.send_sysex(data)
+    }
+
+    fn reset(&mut self) -> Result<(), KdmapiError> {
+        self.inner.reset()
+    }
+}
+
+struct Gate(Arc<(Mutex<(bool, bool)>, Condvar)>);
+
+impl Gate {
+    fn wait_entered(&self) {
+        let (lock, cond) = &*self.0;
+        let mut state = lock.lock().unwrap();
+        while !state.0 {
+            state = cond.wait(state).unwrap();
+        }
+    }
+
+    fn open(&self) {
+        let (lock, cond) = &*self.0;
+        lock.lock().unwrap().1 = true;
+        cond.notify_all();
+    }
+}
+
+/// Fills the queue of a sender thread that is stuck on the first event, and
+/// sends two more
+fn overflow(policy: OverflowPolicy) -> (Vec<Result<(), QueueError>>, Vec<u32>, u64) {
+    let (sink, gate) = GatedSink::new();
+    let thread = SenderThread::spawn(
+        sink,
+        SenderOptions {
+            capacity: 4,
+            overflow: policy,
+        },
+    );
+    let sender = thread.sender();
+    sender.send(0x000090).unwrap();
+    gate.wait_entered();
+
+    let results = (1..=6).map(|i| sender.send(0x90 | i << 8)).collect();
+    gate.open();
+    let dropped = thread.stats().events_dropped;
+    let sink = thread.into_sink();
+    (results, sink.inner.short_messages(), dropped)
+}
+
+#[test]
+fn drop_newest_rejects_events_when_full() {
+    let (results, messages, dropped) = overflow(OverflowPolicy::DropNewest);
+    assert_eq!(
+        results,
+        [
+            Ok(()),
+            Ok(()),
+            Ok(()),
+            Ok(()),
+            Err(QueueError::Full),
+            Err(QueueError::Full)
+        ]
+    );
+    assert_eq!(messages, [0x000090, 0x000190, 0x000290, 0x000390, 0x000490]);
+    assert_eq!(dropped, 2);
+}
+
+#[test]
+fn drop_oldest_makes_room_when_full() {
+    let (results, messages, dropped) = overflow(OverflowPolicy::DropOldest);
+    assert!(results.iter().all(Result::is_ok));
+    assert_eq!(messages, [0x000090, 0x000390, 0x000490, 0x000590, 0x000690]);
+    assert_eq!(dropped, 2);
+}
+
+#[test]
+fn block_waits_for_room() {
+    let (sink, gate) = GatedSink::new();
+    let thread = SenderThread::spawn(
+        sink,
+        SenderOptions {
+            capacity: 2,
+            overflow: OverflowPolicy::Block,
+        },
+    );
+    let sender = thread.sender();
+    sender.send(0x000090).unwrap();
+    gate.wait_entered();
+    sender.send(0x000190).unwrap();
+    sender.send(0x000290).unwrap();
+
+    let done = Arc::new(AtomicBool::new(false));
+    let blocked = {
+        let sender = sender.clone();
+        let done = done.clone();
+        thread::spawn(move || {
+            sender.send(0x000390).unwrap();
+            done.store(true, Ordering::SeqCst);
+        })
+    };
+    thread::sleep(Duration::from_millis(50));
+    assert!(!done.load(Ordering::SeqCst));
+
+    gate.open();
+    blocked.join().unwrap();
+    let sink = thread.into_sink();
+    assert_eq!(
+        sink.inner.short_messages(),
+        [0x000090, 0x000190, 0x000290, 0x000390]
+    );
+    assert_eq!(sender.send(0x000490), Err(QueueError::Closed));
+}
+
+#[test]
+fn events_queued_before_closing_are_sent() {
+    let thread = SenderThread::spawn(RecordingSink::new(), SenderOptions::default());
+    let producers: Vec<_> = (0..4u32)
+        .map(|producer| {
+            let sender = thread.sender();
+            thread::spawn(move || {
+                let mut queued = 0;
+                while sender.send(0x90 | producer).is_ok() {
+                    queued += 1;
+                }
+                queued
+            })
+        })
+        .collect();
+    thread::sleep(Duration::from_millis(20));
+
+    let sink = thread.into_sink();
+    let queued: usize = producers.into_iter().map(|p| p.join().unwrap()).sum();
+    assert_eq!(sink.short_messages().len(), queued);
+}
+
+struct PanickingSink;
+
+impl MidiSink for PanickingSink {
+    fn send_short(&mut self, _data: u32) -> Result<(), KdmapiError> {
+        panic!("sink failed");
+    }
+
+    fn send_short_no_buf(&mut self, _data: u32) -> Result<(), KdmapiError> {
+        panic!("sink failed");
+    }
+
+    fn send_sysex(&mut self, _data: &[u8]) -> Result<(), KdmapiError> {
+        panic!("sink failed");
+    }
+
+    fn reset(&mut self) -> Result<(), KdmapiError> {
+        Ok(())
+    }
+}
+
+#[test]
+fn blocked_senders_fail_when_the_sink_panics() {
+    let thread = SenderThread::spawn(
+        PanickingSink,
+        SenderOptions {
+            capacity: 2,
+            overflow: OverflowPolicy::Block,
+        },
+    );
+    let sender = thread.sender();
+    let result = (0..16).map(|_| sender.send(0x000090)).find(Result::is_err);
+    assert_eq!(result, Some(Err(QueueError::Closed)));
+    assert!(sender.is_closed());
+}
+
+#[test]
+#[should_panic(expected = "the sender thread panicked")]
+fn into_sink_reports_a_panicked_sink() {
+    let thread = SenderThread::spawn(PanickingSink, SenderOptions::default());
+    thread.sender().send(0x000090).unwrap();
+    thread.into_sink();
+}
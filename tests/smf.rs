@@ -0,0 +1,528 @@
+use std::{
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+use kdmapi::{
+    smf::{EventKind, Smf, SmfError, StreamingOptions, StreamingSmf, TimedEvent, Timing},
+    Clock, KdmapiError, MidiPlayer, MidiSink, PlaybackState, RecordingSink, SinkEvent,
+    StreamingPlayer, VirtualClock,
+};
+
+fn chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut chunk = id.to_vec();
+    chunk.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    chunk.extend_from_slice(data);
+    chunk
+}
+
+/// Format 1, 96 ticks per quarter. Track 0 halves the tempo at tick 96,
+/// track 1 plays two notes using running status.
+fn test_file() -> Vec<u8> {
+    let mut file = chunk(b"MThd", &[0, 1, 0, 2, 0, 96]);
+    file.extend(chunk(
+        b"MTrk",
+        &[
+            0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20, // 500000, 120 BPM
+            0x60, 0xFF, 0x51, 0x03, 0x0F, 0x42, 0x40, // 1000000, 60 BPM
+            0x00, 0xFF, 0x2F, 0x00,
+        ],
+    ));
+    file.extend(chunk(
+        b"MTrk",
+        &[
+            0x00, 0x90, 0x3C, 0x64, // note on
+            0x60, 0x3C, 0x00, // running status
+            0x81, 0x40, 0x80, 0x3C, 0x40, // delta 192
+            0x00, 0xF0, 0x03, 0x7E, 0x01, 0xF7, // SysEx
+            0x00, 0xFF, 0x2F, 0x00,
+        ],
+    ));
+    file
+}
+
+#[test]
+fn parses_tracks_and_running_status() {
+    let smf = Smf::parse(&test_file()).unwrap();
+
+    assert_eq!(smf.format, 1);
+    assert_eq!(smf.timing, Timing::TicksPerQuarter(96));
+    assert_eq!(smf.tracks.len(), 2);
+
+    let kinds: Vec<_> = smf.tracks[1].events.iter().map(|e| &e.kind).collect();
+    assert_eq!(kinds[0], &EventKind::Midi(0x643C90));
+    assert_eq!(kinds[1], &EventKind::Midi(0x003C90));
+    assert_eq!(kinds[2], &EventKind::Midi(0x403C80));
+    assert_eq!(kinds[3], &EventKind::SysEx(vec![0xF0, 0x7E, 0x01, 0xF7]));
+    assert_eq!(smf.tracks[1].events[2].delta, 192);
+}
+
+#[test]
+fn applies_the_tempo_map() {
+    let smf = Smf::parse(&test_file()).unwrap();
+    let times: Vec<_> = smf
+        .merged_events()
+        .into_iter()
+        .filter(|e| matches!(e.kind, EventKind::Midi(_)))
+        .map(|e| e.time)
+        .collect();
+
+    assert_eq!(
+        times,
+        vec![
+            Duration::ZERO,
+            Duration::from_millis(500),
+            Duration::from_millis(2500)
+        ]
+    );
+}
+
+#[test]
+fn rejects_other_files() {
+    assert!(matches!(
+        Smf::parse(b"RIFF\0\0\0\0"),
+        Err(SmfError::NotMidiFile)
+    ));
+    assert!(matches!(
+        Smf::parse(&chunk(b"MThd", &[0, 2, 0, 1, 0, 96])),
+        Err(SmfError::UnsupportedFormat(2))
+    ));
+}
+
+#[test]
+fn player_sends_every_event() {
+    let mut file = chunk(b"MThd", &[0, 0, 0, 1, 0, 96]);
+    file.extend(chunk(
+        b"MTrk",
+        &[
+            0x00, 0x90, 0x3C, 0x64, 0x0A, 0x80, 0x3C, 0x00, 0x00, 0xFF, 0x2F, 0x00,
+        ],
+    ));
+    let smf = Smf::parse(&file).unwrap();
+
+    let player = MidiPlayer::new(RecordingSink::new(), &smf);
+    player.play();
+    while player.state() == PlaybackState::Playing {
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(player.state(), PlaybackState::Finished);
+
+    let sink = player.into_sink();
+    assert_eq!(sink.short_messages(), vec![0x643C90, 0x003C80]);
+}
+
+fn write_temp_file(name: &str, data: &[u8]) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("kdmapi-{}-{}.mid", name, std::process::id()));
+    std::fs::write(&path, data).unwrap();
+    path
+}
+
+/// Many tracks with events interleaved across them
+fn many_tracks_file(tracks: u16, notes: usize) -> Vec<u8> {
+    let mut file = chunk(b"MThd", &[0, 1, (tracks >> 8) as u8, tracks as u8, 0, 96]);
+    for track in 0..tracks {
+        let mut data = Vec::new();
+        for note in 0..notes {
+            let delta = if note == 0 { track as u8 % 4 } else { 3 };
+            data.extend_from_slice(&[delta, 0x90, (note % 128) as u8, 0x40]);
+        }
+        data.extend_from_slice(&[0x00, 0xFF, 0x2F, 0x00]);
+        file.extend(chunk(b"MTrk", &data));
+    }
+    file
+}
+
+#[test]
+fn streaming_matches_in_memory_merge() {
+    let data = many_tracks_file(37, 500);
+    let path = write_temp_file("streaming", &data);
+
+    let options = StreamingOptions {
+        workers: 3,
+        batch_size: 16,
+        buffered_batches: 1,
+        read_buffer_size: 64,
+    };
+    let streaming = StreamingSmf::open(&path).unwrap();
+    assert_eq!(streaming.track_count(), 37);
+    let streamed: Vec<_> = streaming
+        .events(&options)
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(streamed, Smf::parse(&data).unwrap().merged_events());
+
+    // Also make sure the tempo map is applied while streaming
+    let path = write_temp_file("streaming-tempo", &test_file());
+    let streamed: Vec<_> = StreamingSmf::open(&path)
+        .unwrap()
+        .events(&StreamingOptions::default())
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(streamed, Smf::parse(&test_file()).unwrap().merged_events());
+}
+
+#[test]
+fn streaming_player_sends_in_order() {
+    let data = many_tracks_file(4, 3);
+    let smf = Smf::parse(&data).unwrap();
+    let events = smf.merged_events();
+
+    let mut player = StreamingPlayer::new(RecordingSink::new());
+    let stats = player.play(events.iter().cloned().map(Ok)).unwrap();
+    assert_eq!(stats.events_sent, 12);
+
+    let expected: Vec<_> = events
+        .iter()
+        .filter_map(|e| match e.kind {
+            EventKind::Midi(data) => Some(data),
+            _ => None,
+        })
+        .collect();
+    // Followed by sustain off, All Sound Off and All Notes Off on every
+    // channel
+    let sent = player.into_sink().short_messages();
+    assert_eq!(&sent[..12], expected.as_slice());
+    let silence: Vec<_> = (0..16)
+        .flat_map(|ch| vec![0x0040B0 | ch, 0x0078B0 | ch, 0x007BB0 | ch])
+        .collect();
+    assert_eq!(&sent[12..], silence.as_slice());
+}
+
+/// Records the size of every batch, and SysEx as a batch of 0
+#[derive(Default)]
+struct BatchSink {
+    batches: Vec<usize>,
+}
+
+impl MidiSink for BatchSink {
+    fn send_short(&mut self, _: u32) -> Result<(), KdmapiError> {
+        self.batches.push(1);
+        Ok(())
+    }
+
+    fn send_short_no_buf(&mut self, data: u32) -> Result<(), KdmapiError> {
+        self.send_short(data)
+    }
+
+    fn send_sysex(&mut self, _: &[u8]) -> Result<(), KdmapiError> {
+        self.batches.push(0);
+        Ok(())
+    }
+
+    fn send_short_batch(&mut self, data: &[u32]) -> Result<(), KdmapiError> {
+        self.batches.push(data.len());
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Result<(), KdmapiError> {
+        Ok(())
+    }
+}
+
+#[test]
+fn streaming_player_batches_due_events() {
+    let event = |time_ms, kind| TimedEvent {
+        tick: 0,
+        time: Duration::from_millis(time_ms),
+        track: 0,
+        kind,
+    };
+    let note = |key: u32| EventKind::Midi(0x400090 | key << 8);
+    let events = vec![
+        event(0, note(60)),
+        event(0, note(64)),
+        event(0, EventKind::SysEx(vec![0xF0, 0x7E, 0xF7])),
+        event(0, note(67)),
+        event(0, note(72)),
+    ];
+
+    let mut player = StreamingPlayer::new(BatchSink::default());
+    let stats = player.play(events.into_iter().map(Ok)).unwrap();
+    assert_eq!(stats.events_sent, 5);
+    // Then one call per silencing message
+    assert_eq!(player.into_sink().batches[..3], [2, 0, 2]);
+}
+
+/// Forwards short messages with their time on the clock, so a test can
+/// wait for them
+struct ChannelSink {
+    clock: VirtualClock,
+    start: Instant,
+    sent: mpsc::Sender<(Duration, u32)>,
+}
+
+impl MidiSink for ChannelSink {
+    fn send_short(&mut self, data: u32) -> Result<(), KdmapiError> {
+        let _ = self.sent.send((self.clock.now() - self.start, data));
+        Ok(())
+    }
+
+    fn send_short_no_buf(&mut self, data: u32) -> Result<(), KdmapiError> {
+        self.send_short(data)
+    }
+
+    fn send_sysex(&mut self, _: &[u8]) -> Result<(), KdmapiError> {
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Result<(), KdmapiError> {
+        Ok(())
+    }
+}
+
+#[test]
+fn streaming_player_follows_the_clock() {
+    let event = |time_ms, key: u32| TimedEvent {
+        tick: 0,
+        time: Duration::from_millis(time_ms),
+        track: 0,
+        kind: EventKind::Midi(0x400090 | key << 8),
+    };
+    let events = vec![event(0, 60), event(100, 62), event(200, 64)];
+
+    let clock = VirtualClock::new();
+    let (sent, received) = mpsc::channel();
+    let sink = ChannelSink {
+        clock: clock.clone(),
+        start: clock.now(),
+        sent,
+    };
+    let mut player = StreamingPlayer::with_clock(sink, clock.clone())
+        .skip_late_notes(Some(Duration::from_millis(20)));
+    let playing = std::thread::spawn(move || player.play(events.into_iter().map(Ok)).unwrap());
+
+    assert_eq!(received.recv().unwrap(), (Duration::ZERO, 0x403C90));
+    clock.advance(Duration::from_millis(100));
+    assert_eq!(
+        received.recv().unwrap(),
+        (Duration::from_millis(100), 0x403E90)
+    );
+    // 50ms behind when it's sent, so the last note is skipped
+    clock.advance(Duration::from_millis(150));
+    let stats = playing.join().unwrap();
+    assert_eq!(stats.events_sent, 2);
+    assert_eq!(stats.notes_skipped, 1);
+    assert_eq!(stats.max_lag, Duration::from_millis(50));
+    assert_eq!(
+        received.recv().unwrap(),
+        (Duration::from_millis(250), 0x0040B0)
+    );
+}
+
+/// 96 ticks per quarter at 120 BPM, so 192 ticks per second. Sets up channel
+/// 0, plays a note at 0s and another at 1s.
+fn chase_file() -> Vec<u8> {
+    let mut file = chunk(b"MThd", &[0, 0, 0, 1, 0, 96]);
+    file.extend(chunk(
+        b"MTrk",
+        &[
+            0x00, 0xB0, 0x07, 0x64, // volume 100
+            0x00, 0xC0, 0x05, // program 5
+            0x00, 0x90, 0x3C, 0x40, // note on
+            0x81, 0x40, 0xB0, 0x07, 0x50, // volume 80 at 1s
+            0x00, 0x90, 0x3E, 0x40, // note on
+            0x60, 0x80, 0x3C, 0x00, // note offs
+            0x00, 0x80, 0x3E, 0x00, //
+            0x00, 0xFF, 0x2F, 0x00,
+        ],
+    ));
+    file
+}
+
+#[test]
+fn seeking_chases_channel_state() {
+    let smf = Smf::parse(&chase_file()).unwrap();
+    let player = MidiPlayer::new(RecordingSink::new(), &smf);
+
+    player.seek(Duration::from_millis(500));
+    assert_eq!(player.state(), PlaybackState::Paused);
+    assert_eq!(player.position(), Duration::from_millis(500));
+
+    // Sustain off and a reset on every channel, then the chased state
+    let sent = player.into_sink().short_messages();
+    let sustain_off: Vec<_> = (0..16).map(|ch| 0x0040B0 | ch).collect();
+    assert_eq!(&sent[..16], sustain_off.as_slice());
+    assert_eq!(&sent[16..48], channel_resets().as_slice());
+    assert_eq!(&sent[48..], &[0x05C0, 0x6407B0]);
+}
+
+/// Reset All Controllers and a centered pitch bend on every channel
+fn channel_resets() -> Vec<u32> {
+    (0..16)
+        .flat_map(|ch| [0x0079B0 | ch, 0x4000E0 | ch])
+        .collect()
+}
+
+#[test]
+fn seeking_back_resets_later_controllers() {
+    // 500 ticks per quarter at the default 120 BPM, so one tick is 1 ms
+    let mut file = chunk(b"MThd", &[0, 0, 0, 1, 0x01, 0xF4]);
+    file.extend(chunk(
+        b"MTrk",
+        &[
+            0x00, 0xB0, 0x07, 0x64, // volume 100
+            0x64, 0xE0, 0x00, 0x60, // pitch bend up at 100ms
+            0x00, 0xB0, 0x07, 0x32, // volume 50
+            0x64, 0xFF, 0x2F, 0x00, // end at 200ms
+        ],
+    ));
+    let smf = Smf::parse(&file).unwrap();
+    let clock = VirtualClock::new();
+    let player = MidiPlayer::with_clock(
+        RecordingSink::with_clock(clock.clone()),
+        &smf,
+        clock.clone(),
+    );
+
+    player.play();
+    player.wait_due();
+    clock.advance(Duration::from_millis(150));
+    player.wait_due();
+    player.seek(Duration::from_millis(50));
+    player.wait_due();
+
+    let sent = player.into_sink().short_messages();
+    assert_eq!(&sent[..4], &[0x6407B0, 0x6000E0, 0x3207B0, 0x0040B0]);
+    // Sustain off, the reset, then volume 100 again without the bend
+    let chased = &sent[3..];
+    assert_eq!(&chased[16..48], channel_resets().as_slice());
+    assert_eq!(&chased[48..], &[0x6407B0]);
+}
+
+#[test]
+fn seeking_by_tick_uses_the_tempo_map() {
+    let smf = Smf::parse(&chase_file()).unwrap();
+    let player = MidiPlayer::new(RecordingSink::new(), &smf);
+
+    player.seek_tick(192);
+    assert_eq!(player.position(), Duration::from_secs(1));
+
+    // The volume change at exactly 1s hasn't been sent yet
+    let sent = player.into_sink().short_messages();
+    assert_eq!(&sent[16..48], channel_resets().as_slice());
+    assert_eq!(&sent[48..], &[0x05C0, 0x6407B0]);
+}
+
+/// Note ons sent to `sink`, with their time since `start`
+fn note_ons(sink: &RecordingSink<VirtualClock>, start: Instant) -> Vec<(Duration, u32)> {
+    sink.events()
+        .iter()
+        .filter_map(|event| match event.event {
+            SinkEvent::Short(data) if data & 0xF0 == 0x90 => Some((event.time - start, data)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn speed_scales_playback() {
+    let smf = Smf::parse(&chase_file()).unwrap();
+    let clock = VirtualClock::new();
+    let start = clock.now();
+    let sink = RecordingSink::with_clock(clock.clone());
+    let player = MidiPlayer::with_clock(sink, &smf, clock.clone());
+
+    player.set_speed(10.0);
+    assert_eq!(player.speed(), kdmapi::MAX_SPEED);
+
+    // 1.5s of song at 4x
+    player.play();
+    for step in [250, 124] {
+        player.wait_due();
+        clock.advance(Duration::from_millis(step));
+    }
+    player.wait_due();
+    assert_eq!(player.state(), PlaybackState::Playing);
+    assert_eq!(player.position(), Duration::from_millis(1496));
+    clock.advance(Duration::from_millis(1));
+    player.wait_due();
+    assert_eq!(player.state(), PlaybackState::Finished);
+
+    assert_eq!(
+        note_ons(&player.into_sink(), start),
+        [
+            (Duration::ZERO, 0x403C90),
+            (Duration::from_millis(250), 0x403E90)
+        ]
+    );
+}
+
+#[test]
+fn looping_repeats_the_range() {
+    let smf = Smf::parse(&chase_file()).unwrap();
+    let clock = VirtualClock::new();
+    let start = clock.now();
+    let sink = RecordingSink::with_clock(clock.clone());
+    let player = MidiPlayer::with_clock(sink, &smf, clock.clone());
+
+    // 1.2s of song at 4x, so a loop takes 300ms
+    player.set_speed(4.0);
+    player.set_loop(Some(Duration::ZERO..Duration::from_millis(1200)));
+    player.play();
+    for step in [250, 50, 250, 50, 100] {
+        player.wait_due();
+        clock.advance(Duration::from_millis(step));
+    }
+    player.wait_due();
+    assert_eq!(player.state(), PlaybackState::Playing);
+    assert_eq!(player.position(), Duration::from_millis(400));
+    player.stop();
+
+    assert_eq!(
+        note_ons(&player.into_sink(), start),
+        [
+            (Duration::ZERO, 0x403C90),
+            (Duration::from_millis(250), 0x403E90),
+            (Duration::from_millis(300), 0x403C90),
+            (Duration::from_millis(550), 0x403E90),
+            (Duration::from_millis(600), 0x403C90),
+        ]
+    );
+}
+
+struct PanickingSink;
+
+impl MidiSink for PanickingSink {
+    fn send_short(&mut self, _data: u32) -> Result<(), KdmapiError> {
+        panic!("sink failed");
+    }
+
+    fn send_short_no_buf(&mut self, _data: u32) -> Result<(), KdmapiError> {
+        panic!("sink failed");
+    }
+
+    fn send_sysex(&mut self, _data: &[u8]) -> Result<(), KdmapiError> {
+        panic!("sink failed");
+    }
+
+    fn reset(&mut self) -> Result<(), KdmapiError> {
+        Ok(())
+    }
+}
+
+#[test]
+fn waiting_stops_when_the_player_sink_panics() {
+    let smf = Smf::parse(&chase_file()).unwrap();
+    let clock = VirtualClock::new();
+    let player = MidiPlayer::with_clock(PanickingSink, &smf, clock.clone());
+    player.play();
+    player.wait_due();
+    clock.advance(Duration::from_secs(1));
+    player.wait_due();
+}
+
+#[test]
+#[should_panic(expected = "the player thread panicked")]
+fn player_into_sink_reports_a_panicked_sink() {
+    let smf = Smf::parse(&chase_file()).unwrap();
+    let clock = VirtualClock::new();
+    let player = MidiPlayer::with_clock(PanickingSink, &smf, clock);
+    player.play();
+    player.wait_due();
+    player.into_sink();
+}
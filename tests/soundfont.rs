@@ -0,0 +1,432 @@
+use std::{fs, path::PathBuf};
+
+use kdmapi::soundfont::{
+    ListIssue, Sf2, Sf2Error, Sf2Preset, Sfz, SfzProblem, SfzProblemKind, SoundFontEntry,
+    SoundFontList, SoundFontListError,
+};
+
+/// A RIFF chunk, padded to an even length
+fn chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut chunk = id.to_vec();
+    chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    chunk.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        chunk.push(0);
+    }
+    chunk
+}
+
+fn list(kind: &[u8], chunks: &[Vec<u8>]) -> Vec<u8> {
+    let mut data = kind.to_vec();
+    chunks.iter().for_each(|sub| data.extend_from_slice(sub));
+    chunk(b"LIST", &data)
+}
+
+/// An SF2 file with the given (name, bank, program) presets and an odd
+/// number of sample bytes
+fn sf2_file(presets: &[(&str, u16, u16)]) -> Vec<u8> {
+    let mut headers = Vec::new();
+    for (name, bank, program) in presets.iter().chain(Some(&("EOP", 0, 0))) {
+        let mut record = [0; 38];
+        record[..name.len()].copy_from_slice(name.as_bytes());
+        record[20..22].copy_from_slice(&program.to_le_bytes());
+        record[22..24].copy_from_slice(&bank.to_le_bytes());
+        headers.extend_from_slice(&record);
+    }
+
+    let mut data = b"sfbk".to_vec();
+    data.extend(list(
+        b"INFO",
+        &[chunk(b"ifil", &[2, 0, 1, 0]), chunk(b"INAM", b"Test Font")],
+    ));
+    data.extend(list(b"sdta", &[chunk(b"smpl", &[1; 7])]));
+    data.extend(list(
+        b"pdta",
+        &[chunk(b"phdr", &headers), chunk(b"pbag", &[0; 4])],
+    ));
+    chunk(b"RIFF", &data)
+}
+
+/// An empty directory for a test's files
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("kdmapi-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+const LIST: &str = "sf.start\r
+sf.path = C:\\SoundFonts\\Piano Collection.sf2\r
+sf.enabled = 1\r
+sf.preload = 1\r
+sf.srcb = 0\r
+sf.srcp = 3\r
+sf.desb = 8\r
+sf.desp = 0\r
+sf.xgdrums = 0\r
+sf.end\r
+\r
+sf.start\r
+sf.path = C:\\SoundFonts\\drums.sfz\r
+sf.enabled = 0\r
+sf.srcb = -1\r
+sf.srcp = -1\r
+sf.desb = -1\r
+sf.desp = -1\r
+sf.xgdrums = 1\r
+sf.linattmod = 0\r
+sf.end\r
+";
+
+#[test]
+fn parses_entries_in_order() {
+    let list = SoundFontList::parse(LIST).unwrap();
+
+    let piano = SoundFontEntry {
+        path: PathBuf::from("C:\\SoundFonts\\Piano Collection.sf2"),
+        enabled: true,
+        preload: true,
+        source_bank: Some(0),
+        source_preset: Some(3),
+        destination_bank: Some(8),
+        destination_preset: Some(0),
+        xg_drums: false,
+    };
+    let drums = SoundFontEntry {
+        enabled: false,
+        destination_bank: None,
+        xg_drums: true,
+        ..SoundFontEntry::new("C:\\SoundFonts\\drums.sfz")
+    };
+    assert_eq!(list.entries, [piano, drums]);
+}
+
+#[test]
+fn round_trips_through_text_and_files() {
+    let list = SoundFontList::parse(LIST).unwrap();
+    assert_eq!(
+        SoundFontList::parse(&list.to_text().unwrap()).unwrap(),
+        list
+    );
+
+    let path = test_dir("round-trip").join("list.sflist");
+    list.save(&path).unwrap();
+    assert_eq!(SoundFontList::open(&path).unwrap(), list);
+
+    let mut entry = SoundFontEntry::new("/usr/share/soundfonts/default.sf2");
+    entry.destination_preset = Some(127);
+    let list = SoundFontList {
+        entries: vec![entry],
+    };
+    assert_eq!(
+        list.to_text().unwrap(),
+        "sf.start\n\
+         sf.path = /usr/share/soundfonts/default.sf2\n\
+         sf.enabled = 1\n\
+         sf.preload = 0\n\
+         sf.srcb = -1\n\
+         sf.srcp = -1\n\
+         sf.desb = 0\n\
+         sf.desp = 127\n\
+         sf.xgdrums = 0\n\
+         sf.end\n"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn refuses_to_serialize_non_utf8_paths() {
+    use std::{ffi::OsStr, io, os::unix::ffi::OsStrExt};
+
+    let list = SoundFontList {
+        entries: vec![SoundFontEntry::new(OsStr::from_bytes(b"/sf/\xFF.sf2"))],
+    };
+    assert_eq!(
+        list.to_text().unwrap_err().kind(),
+        io::ErrorKind::InvalidData
+    );
+    let path = test_dir("non-utf8").join("list.sflist");
+    assert!(list.save(&path).is_err());
+    assert!(!path.exists());
+}
+
+#[test]
+fn reads_legacy_path_lists() {
+    let list = SoundFontList::parse("C:\\a.sf2\n@C:\\b.sf2\n").unwrap();
+
+    let mut disabled = SoundFontEntry::new("C:\\b.sf2");
+    disabled.enabled = false;
+    assert_eq!(list.entries, [SoundFontEntry::new("C:\\a.sf2"), disabled]);
+}
+
+#[test]
+fn rejects_malformed_lists() {
+    let parse = |text: &str| SoundFontList::parse(text).unwrap_err();
+
+    assert!(matches!(
+        parse("sf.start\nsf.path = a.sf2\n"),
+        SoundFontListError::UnbalancedBlock { line: 1 }
+    ));
+    assert!(matches!(
+        parse("sf.end\n"),
+        SoundFontListError::UnbalancedBlock { line: 1 }
+    ));
+    assert!(matches!(
+        parse("sf.start\nsf.enabled = 1\nsf.end\n"),
+        SoundFontListError::MissingPath { line: 3 }
+    ));
+    assert!(matches!(
+        parse("sf.start\nsf.paths = a.sf2\nsf.end\n"),
+        SoundFontListError::MissingPath { line: 3 }
+    ));
+    assert!(matches!(
+        parse("sf.start\nsf.path a.sf2\nsf.end\n"),
+        SoundFontListError::InvalidLine { line: 2 }
+    ));
+    assert!(matches!(
+        parse("sf.start\nsf.path = a.sf2\nsf.srcb = 129\nsf.end\n"),
+        SoundFontListError::InvalidValue { line: 3, key } if key == "sf.srcb"
+    ));
+    assert!(matches!(
+        parse("sf.start\nsf.path = a.sf2\nsf.enabled = yes\nsf.end\n"),
+        SoundFontListError::InvalidValue { line: 3, .. }
+    ));
+    assert!(matches!(
+        SoundFontList::open("/nonexistent/list.sflist"),
+        Err(SoundFontListError::Io(_))
+    ));
+}
+
+#[test]
+fn reads_sf2_presets() {
+    let data = sf2_file(&[("Grand Piano", 0, 0), ("Standard", 128, 0)]);
+    let sf2 = Sf2::parse(&data).unwrap();
+
+    assert_eq!(sf2.version, (2, 1));
+    assert_eq!(sf2.name, "Test Font");
+    assert_eq!(
+        sf2.presets,
+        [
+            Sf2Preset {
+                name: "Grand Piano".to_owned(),
+                bank: 0,
+                program: 0,
+            },
+            Sf2Preset {
+                name: "Standard".to_owned(),
+                bank: 128,
+                program: 0,
+            },
+        ]
+    );
+    assert_eq!(sf2.preset(128, 0).unwrap().name, "Standard");
+    assert!(sf2.preset(0, 1).is_none());
+
+    let path = test_dir("sf2").join("test.sf2");
+    fs::write(&path, &data).unwrap();
+    assert_eq!(Sf2::open(&path).unwrap(), sf2);
+}
+
+#[test]
+fn rejects_broken_sf2_files() {
+    let data = sf2_file(&[("Piano", 0, 0)]);
+
+    assert!(matches!(
+        Sf2::parse(&chunk(b"RIFF", b"WAVEfmt ")),
+        Err(Sf2Error::NotSoundFont)
+    ));
+    assert!(matches!(
+        Sf2::parse(&data[..data.len() - 20]),
+        Err(Sf2Error::UnexpectedEof)
+    ));
+    let no_presets = chunk(b"RIFF", &[&b"sfbk"[..], &list(b"INFO", &[])].concat());
+    assert!(matches!(
+        Sf2::parse(&no_presets),
+        Err(Sf2Error::MissingPresets)
+    ));
+}
+
+#[test]
+fn checks_sfz_files() {
+    let dir = test_dir("sfz");
+    fs::create_dir(dir.join("samples")).unwrap();
+    fs::write(dir.join("samples/piano c4.wav"), b"").unwrap();
+    fs::write(dir.join("samples/piano e4.wav"), b"").unwrap();
+    fs::write(
+        dir.join("regions.sfz"),
+        "<region> sample=$DIR\\piano e4.wav key=64\n<region>sample=missing.wav\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("piano.sfz"),
+        "// A comment <not a header>\n\
+         #define $DIR samples\n\
+         <control> default_path=samples/\n\
+         <group> ampeg_release=0.5 <region> sample=piano c4.wav lokey=0 hikey=62\n\
+         <control> default_path=\n\
+         #include \"regions.sfz\"\n\
+         #include \"missing.sfz\"\n\
+         <region> sample=*sine\n\
+         <instrument> stray\n\
+         <region\n",
+    )
+    .unwrap();
+
+    let sfz = Sfz::open(dir.join("piano.sfz")).unwrap();
+    assert_eq!(sfz.regions, 4);
+    assert_eq!(
+        sfz.samples.iter().cloned().collect::<Vec<_>>(),
+        [
+            dir.join("missing.wav"),
+            dir.join("samples/piano c4.wav"),
+            dir.join("samples/piano e4.wav"),
+        ]
+    );
+    let problem = |file: &str, line, kind| SfzProblem {
+        file: dir.join(file),
+        line,
+        kind,
+    };
+    assert_eq!(
+        sfz.problems,
+        [
+            problem(
+                "regions.sfz",
+                2,
+                SfzProblemKind::MissingSample(dir.join("missing.wav"))
+            ),
+            problem(
+                "piano.sfz",
+                7,
+                SfzProblemKind::MissingInclude(dir.join("missing.sfz"))
+            ),
+            problem(
+                "piano.sfz",
+                9,
+                SfzProblemKind::UnknownHeader("instrument".to_owned())
+            ),
+            problem(
+                "piano.sfz",
+                9,
+                SfzProblemKind::UnexpectedText("stray".to_owned())
+            ),
+            problem("piano.sfz", 10, SfzProblemKind::UnterminatedHeader),
+        ]
+    );
+    assert!(!sfz.is_valid());
+
+    // Include cycles are cut off
+    fs::write(
+        dir.join("loop.sfz"),
+        "<region> sample=*saw\n#include \"loop.sfz\"\n",
+    )
+    .unwrap();
+    let sfz = Sfz::open(dir.join("loop.sfz")).unwrap();
+    assert_eq!(sfz.problems.len(), 1);
+    assert_eq!(sfz.problems[0].kind, SfzProblemKind::IncludeTooDeep);
+}
+
+#[test]
+fn expands_the_longest_matching_define() {
+    let dir = test_dir("sfz-defines");
+    fs::create_dir(dir.join("soft")).unwrap();
+    fs::write(dir.join("soft/c4.wav"), b"").unwrap();
+    fs::write(
+        dir.join("defines.sfz"),
+        "#define $DIR wrong\n\
+         #define $DIRECTORY soft\n\
+         <region> sample=$DIRECTORY/c4.wav\n\
+         <region> sample=$DIR/$UNDEFINED.wav\n",
+    )
+    .unwrap();
+
+    let sfz = Sfz::open(dir.join("defines.sfz")).unwrap();
+    assert_eq!(
+        sfz.samples.iter().cloned().collect::<Vec<_>>(),
+        [dir.join("soft/c4.wav"), dir.join("wrong/$UNDEFINED.wav")]
+    );
+}
+
+#[test]
+fn maps_presets_like_bassmidi() {
+    let mut entry = SoundFontEntry::new("a.sf2");
+    assert_eq!(entry.map_preset(128, 5), Some((128, 5)));
+
+    entry.destination_bank = Some(2);
+    assert_eq!(entry.map_preset(1, 5), Some((3, 5)));
+
+    entry.source_bank = Some(0);
+    entry.source_preset = Some(5);
+    entry.destination_preset = Some(7);
+    assert_eq!(entry.map_preset(0, 5), Some((2, 7)));
+    assert_eq!(entry.map_preset(0, 6), None);
+    assert_eq!(entry.map_preset(1, 5), None);
+}
+
+#[test]
+fn checks_lists_for_conflicts_and_missing_files() {
+    let dir = test_dir("list-check");
+    let path = |name: &str| dir.join(name);
+    fs::write(
+        path("piano.sf2"),
+        sf2_file(&[("Piano", 0, 0), ("Bright", 0, 1)]),
+    )
+    .unwrap();
+    fs::write(
+        path("gm.sf2"),
+        sf2_file(&[
+            ("Piano", 0, 0),
+            ("Bright", 0, 1),
+            ("Organ", 0, 16),
+            ("Drums", 128, 0),
+        ]),
+    )
+    .unwrap();
+    fs::write(path("broken.sf2"), b"RIFF").unwrap();
+    fs::write(path("strings.sfz"), "<region> sample=*sine\n").unwrap();
+    fs::write(path("bad.sfz"), "<region> sample=gone.wav\n").unwrap();
+
+    let mut strings = SoundFontEntry::new(path("strings.sfz"));
+    strings.destination_preset = Some(16);
+    let mut only_organ = SoundFontEntry::new(path("gm.sf2"));
+    only_organ.source_preset = Some(17);
+    let mut disabled = SoundFontEntry::new(path("gone.sf2"));
+    disabled.enabled = false;
+    let list = SoundFontList {
+        entries: vec![
+            SoundFontEntry::new(path("piano.sf2")),
+            strings,
+            SoundFontEntry::new(path("gm.sf2")),
+            SoundFontEntry::new(path("gone.sf2")),
+            disabled,
+            SoundFontEntry::new(path("broken.sf2")),
+            SoundFontEntry::new(path("bad.sfz")),
+            only_organ,
+        ],
+    };
+
+    let issues = list.check();
+    assert_eq!(issues.len(), 6, "{:#?}", issues);
+    assert!(matches!(
+        &issues[0],
+        ListIssue::Shadowed { entry: 2, by: 0, presets } if presets == &[(0, 0), (0, 1)]
+    ));
+    assert!(matches!(
+        &issues[1],
+        ListIssue::Shadowed { entry: 2, by: 1, presets } if presets == &[(0, 16)]
+    ));
+    assert!(matches!(issues[2], ListIssue::MissingFile { entry: 3 }));
+    assert!(matches!(
+        issues[3],
+        ListIssue::InvalidSf2 {
+            entry: 5,
+            error: Sf2Error::UnexpectedEof
+        }
+    ));
+    assert!(matches!(
+        &issues[4],
+        ListIssue::InvalidSfz { entry: 6, problems } if problems.len() == 1
+    ));
+    assert!(matches!(issues[5], ListIssue::NoPresets { entry: 7 }));
+    assert_eq!(issues[2].to_string(), "entry 3: file doesn't exist");
+}